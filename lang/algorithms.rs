@@ -0,0 +1,207 @@
+use super::token::{self, TokenKind};
+
+/// well known algorithms, usable as `@name` and undone with `@name'`
+const ALGORITHMS: [(&str, &str); 17] = [
+    // triggers
+    ("sexy", "R U R' U'"),
+    ("sledgehammer", "R' F R F'"),
+    ("hedgeslammer", "F R' F' R"),
+    // OLL
+    ("Sune", "R U R' U R U2 R'"),
+    ("Antisune", "R U2 R' U' R U' R'"),
+    // PLL
+    ("Tperm", "R U R' U' R' F R2 U' R' U' R U R' F'"),
+    ("Japerm", "R' U L' U2 R U' R' U2 R L U'"),
+    ("Jbperm", "R U R' F' R U R' U' R' F R2 U' R' U'"),
+    ("Yperm", "F R U' R' U' R U R' F' R U R' U' R' F R F'"),
+    ("Uaperm", "R U' R U R U R U' R' U' R2"),
+    ("Ubperm", "R2 U R U R' U' R' U' R' U R'"),
+    ("Hperm", "M2 U M2 U2 M2 U M2"),
+    ("Zperm", "M2 U M2 U M' U2 M2 U2 M' U2"),
+    ("Aaperm", "X R' U R' D2 R U' R' D2 R2 X'"),
+    ("Abperm", "X R2 D2 R U R' D2 R U' R X'"),
+    ("Eperm", "X' R U' R' D R U R' D' R U R' D R U' R' D' X"),
+    ("Fperm", "R' U' F' R U R' U' R' F R2 U' R' U' R U R' U R"),
+];
+
+/// the moves of a built-in algorithm, reversed and inverted when the name ends in `'`
+pub fn lookup(name: &str) -> Option<Vec<TokenKind>> {
+    let (name, inverse) = match name.strip_suffix('\'') {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    let (_, notation) = ALGORITHMS.iter().find(|(n, _)| *n == name)?;
+
+    let (tokens, errors) = token::tokenize(vec![notation.to_string()], 0);
+    debug_assert!(errors.is_empty(), "{} is not valid cubasm", name);
+
+    let moves: Vec<TokenKind> = tokens.into_iter()
+        .map(|t| t.kind)
+        .filter(|k| !matches!(k, TokenKind::SOF | TokenKind::Newline | TokenKind::EOF))
+        .collect();
+
+    Some(match inverse {
+        true => moves.into_iter().rev().map(|k| k.opposite()).collect(),
+        false => moves,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::codegen::{self, Location};
+    use crate::lang::token::Token;
+    use crate::vm::cube::{Cube, B, D, F, L, R, U};
+    use crate::vm::vm::apply_move;
+
+    /// the algorithm made on the cube, through the code generator like any cubasm
+    fn apply(cube: &mut Cube, name: &str) {
+        let kinds = [TokenKind::SOF].into_iter().chain(lookup(name).unwrap()).chain([TokenKind::EOF]);
+        let tokens = kinds.map(|kind| Token { kind, location: Location::default(), len: 0 }).collect();
+        let object = codegen::generate(tokens, vec![], &mut vec![]).unwrap();
+        for moveset in &object.code.movesets[1..] {
+            apply_move(cube, *moveset);
+        }
+    }
+
+    /// how often the algorithm has to be made to solve the cube again
+    fn order(name: &str) -> usize {
+        let mut cube = Cube::new();
+        for n in 1..=1260 {
+            apply(&mut cube, name);
+            if cube == Cube::new() {
+                return n;
+            }
+        }
+        panic!("{} never solves the cube", name);
+    }
+
+    /// whether only the U layer moved, the first row of the side faces is the one next to U
+    fn last_layer_only(cube: &Cube) -> bool {
+        let solved = Cube::new();
+        cube.faces[D] == solved.faces[D] && [F, R, B, L].iter().all(|&face| {
+            (0..9).filter(|i| i % 3 != 0).all(|i| cube.faces[face][i] == solved.faces[face][i])
+        })
+    }
+
+    /// an OLL: it keeps the first two layers
+    fn assert_oll(name: &str, expected_order: usize) {
+        let mut cube = Cube::new();
+        apply(&mut cube, name);
+        assert!(last_layer_only(&cube), "{} moves more than the last layer", name);
+        assert_eq!(order(name), expected_order, "order of {}", name);
+    }
+
+    /// a PLL: it keeps the first two layers and the U face, and only swaps pieces around
+    fn assert_pll(name: &str, expected_order: usize) {
+        let mut cube = Cube::new();
+        apply(&mut cube, name);
+        assert!(last_layer_only(&cube), "{} moves more than the last layer", name);
+        assert_eq!(cube.faces[U], Cube::new().faces[U], "{} turns U stickers away", name);
+        assert_ne!(cube, Cube::new(), "{} does nothing", name);
+        assert_eq!(order(name), expected_order, "order of {}", name);
+    }
+
+    #[test]
+    fn sexy() {
+        assert_eq!(order("sexy"), 6);
+    }
+
+    #[test]
+    fn sledgehammer() {
+        assert_eq!(order("sledgehammer"), 6);
+    }
+
+    #[test]
+    fn hedgeslammer() {
+        assert_eq!(order("hedgeslammer"), 6);
+    }
+
+    #[test]
+    fn sune() {
+        assert_oll("Sune", 6);
+    }
+
+    #[test]
+    fn antisune() {
+        assert_oll("Antisune", 6);
+    }
+
+    #[test]
+    fn t_perm() {
+        assert_pll("Tperm", 2);
+    }
+
+    #[test]
+    fn ja_perm() {
+        assert_pll("Japerm", 2);
+    }
+
+    #[test]
+    fn jb_perm() {
+        assert_pll("Jbperm", 2);
+    }
+
+    #[test]
+    fn y_perm() {
+        assert_pll("Yperm", 2);
+    }
+
+    #[test]
+    fn ua_perm() {
+        assert_pll("Uaperm", 3);
+    }
+
+    #[test]
+    fn ub_perm() {
+        assert_pll("Ubperm", 3);
+    }
+
+    #[test]
+    fn h_perm() {
+        assert_pll("Hperm", 2);
+    }
+
+    #[test]
+    fn z_perm() {
+        assert_pll("Zperm", 2);
+    }
+
+    #[test]
+    fn aa_perm() {
+        assert_pll("Aaperm", 3);
+    }
+
+    #[test]
+    fn ab_perm() {
+        assert_pll("Abperm", 3);
+    }
+
+    #[test]
+    fn e_perm() {
+        assert_pll("Eperm", 2);
+    }
+
+    #[test]
+    fn f_perm() {
+        assert_pll("Fperm", 2);
+    }
+
+    #[test]
+    fn inverses_undo_every_algorithm() {
+        for (name, _) in ALGORITHMS {
+            let mut cube = Cube::new();
+            apply(&mut cube, name);
+            apply(&mut cube, &format!("{}'", name));
+            assert_eq!(cube, Cube::new(), "{}' does not undo {}", name, name);
+        }
+    }
+
+    #[test]
+    fn ua_undoes_ub() {
+        let mut cube = Cube::new();
+        apply(&mut cube, "Uaperm");
+        apply(&mut cube, "Ubperm");
+        assert_eq!(cube, Cube::new());
+    }
+}
@@ -0,0 +1,344 @@
+//! compiled code on disk, a `.cubc` file.
+//!
+//! all little endian:
+//!
+//! | field          | layout                                                         |
+//! | ---            | ---                                                            |
+//! | magic          | `CUBC`                                                         |
+//! | version        | u16                                                            |
+//! | start marker   | u16, `0x00B0`                                                  |
+//! | sticker scheme | u8 per face in U F R B L D order, the stickers of a fresh cube |
+//! | decoder        | u8, the face whose sum is the opcode                           |
+//! | sections       | 4 byte tag, u32 length in bytes, then the section, to the end  |
+//!
+//! the sections are:
+//!
+//! | tag    | section                                                               | required |
+//! | ---    | ---                                                                   | ---      |
+//! | `CODE` | u16 movesets, after the start marker                                  | yes      |
+//! | `SYMS` | u32 count, then a u16 name length, the name and a u16 target each     | no       |
+//! | `FILE` | u32 count, then a u16 path length and the path of each source file    | no       |
+//! | `DBUG` | u32 file, line and column per moveset, starting with the start marker | no       |
+//!
+//! sections with other tags are skipped, so later versions can add to them.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::codegen::{Code, Location};
+use crate::vm::cube::{self, Cube};
+
+const MAGIC: &[u8; 4] = b"CUBC";
+pub const VERSION: u16 = 1;
+const START_MARKER: u16 = 0x00B0;
+
+const CODE: &[u8; 4] = b"CODE";
+const SYMBOLS: &[u8; 4] = b"SYMS";
+const FILES: &[u8; 4] = b"FILE";
+const DEBUG: &[u8; 4] = b"DBUG";
+
+/// the stickers of a fresh cube and the face the opcode is summed from, as this VM decodes them
+fn scheme() -> [u8; 7] {
+    let cube = Cube::new();
+    let mut scheme = [0; 7];
+    for (face, sticker) in scheme.iter_mut().take(6).enumerate() {
+        *sticker = cube.faces[face][4];
+    }
+    scheme[6] = cube::U as u8;
+    scheme
+}
+
+/// writes the code, without its labels and lines when `strip` is set
+pub fn write(code: &Code, w: &mut impl Write, strip: bool) -> io::Result<()> {
+    if code.movesets.first() != Some(&START_MARKER) {
+        return Err(invalid("code does not start with the start marker"));
+    }
+
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&START_MARKER.to_le_bytes())?;
+    w.write_all(&scheme())?;
+
+    let mut section = vec![];
+    for moveset in &code.movesets[1..] {
+        section.extend_from_slice(&moveset.to_le_bytes());
+    }
+    write_section(w, CODE, &section)?;
+
+    if strip {
+        return Ok(());
+    }
+
+    // sorted, so the same code always writes the same file
+    let mut labels: Vec<_> = code.labels.iter().collect();
+    labels.sort();
+
+    let mut section = vec![];
+    section.extend_from_slice(&len_u32(labels.len())?.to_le_bytes());
+    for (name, target) in labels {
+        let len: u16 = name.len().try_into().map_err(|_| invalid("label name is too long"))?;
+        section.extend_from_slice(&len.to_le_bytes());
+        section.extend_from_slice(name.as_bytes());
+        section.extend_from_slice(&target.to_le_bytes());
+    }
+    write_section(w, SYMBOLS, &section)?;
+
+    let mut section = vec![];
+    section.extend_from_slice(&len_u32(code.files.len())?.to_le_bytes());
+    for file in &code.files {
+        let len: u16 = file.len().try_into().map_err(|_| invalid("source path is too long"))?;
+        section.extend_from_slice(&len.to_le_bytes());
+        section.extend_from_slice(file.as_bytes());
+    }
+    write_section(w, FILES, &section)?;
+
+    let mut section = vec![];
+    for location in &code.locations {
+        for value in [location.file, location.line, location.column] {
+            section.extend_from_slice(&len_u32(value)?.to_le_bytes());
+        }
+    }
+    write_section(w, DEBUG, &section)
+}
+
+pub fn read(r: &mut impl Read) -> io::Result<Code> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not cubvm bytecode"));
+    }
+
+    let mut header = [0u8; 4];
+    r.read_exact(&mut header)?;
+    if u16::from_le_bytes([header[0], header[1]]) != VERSION {
+        return Err(invalid("unsupported bytecode version"));
+    }
+    if u16::from_le_bytes([header[2], header[3]]) != START_MARKER {
+        return Err(invalid("missing the 0x00B0 start marker"));
+    }
+
+    let mut scheme_read = [0u8; 7];
+    r.read_exact(&mut scheme_read)?;
+    if scheme_read != scheme() {
+        return Err(invalid("bytecode was built for a different sticker scheme"));
+    }
+
+    let mut code = Code { movesets: vec![START_MARKER], ..Code::default() };
+    let mut has_code = false;
+
+    loop {
+        let mut tag = [0u8; 4];
+        match r.read_exact(&mut tag) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        // read as far as the file goes rather than allocating whatever the length says up front
+        let len = read_u32(r)?;
+        let mut section = vec![];
+        r.take(len.into()).read_to_end(&mut section)?;
+        if section.len() != len as usize {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "section is cut off"));
+        }
+
+        match &tag {
+            CODE => {
+                if !section.len().is_multiple_of(2) {
+                    return Err(invalid("code section ends halfway through a moveset"));
+                }
+                code.movesets.extend(section.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]])));
+                has_code = true;
+            }
+            SYMBOLS => code.labels = read_symbols(&section)?,
+            FILES => code.files = read_files(&section)?,
+            DEBUG => code.locations = read_locations(&section),
+            _ => {} // from a later version
+        }
+    }
+
+    if !has_code {
+        return Err(invalid("missing the code section"));
+    }
+    Ok(code)
+}
+
+pub fn save(code: &Code, path: impl AsRef<Path>, strip: bool) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write(code, &mut w, strip)?;
+    w.flush()
+}
+
+pub fn load(path: impl AsRef<Path>) -> io::Result<Code> {
+    read(&mut BufReader::new(File::open(path)?))
+}
+
+/// whether the file starts like bytecode rather than source
+pub fn is_bytecode(path: impl AsRef<Path>) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path).and_then(|mut f| f.read_exact(&mut magic)).is_ok() && &magic == MAGIC
+}
+
+fn read_symbols(section: &[u8]) -> io::Result<HashMap<String, u16>> {
+    let mut r = section;
+    let mut labels = HashMap::new();
+
+    let count = read_u32(&mut r)?;
+    for _ in 0..count {
+        let len = read_u16(&mut r)? as usize;
+        let mut name = vec![0u8; len];
+        r.read_exact(&mut name)?;
+        let name = String::from_utf8(name).map_err(|_| invalid("label name is not UTF-8"))?;
+        labels.insert(name, read_u16(&mut r)?);
+    }
+    Ok(labels)
+}
+
+fn read_files(section: &[u8]) -> io::Result<Vec<String>> {
+    let mut r = section;
+    let mut files = vec![];
+
+    let count = read_u32(&mut r)?;
+    for _ in 0..count {
+        let len = read_u16(&mut r)? as usize;
+        let mut path = vec![0u8; len];
+        r.read_exact(&mut path)?;
+        files.push(String::from_utf8(path).map_err(|_| invalid("source path is not UTF-8"))?);
+    }
+    Ok(files)
+}
+
+fn read_locations(section: &[u8]) -> Vec<Location> {
+    let values: Vec<usize> = section.chunks_exact(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize).collect();
+    values.chunks_exact(3).map(|l| Location { file: l[0], line: l[1], column: l[2] }).collect()
+}
+
+fn write_section(w: &mut impl Write, tag: &[u8; 4], section: &[u8]) -> io::Result<()> {
+    w.write_all(tag)?;
+    w.write_all(&len_u32(section.len())?.to_le_bytes())?;
+    w.write_all(section)
+}
+
+fn len_u32(value: usize) -> io::Result<u32> {
+    value.try_into().map_err(|_| invalid("value does not fit in 32 bits"))
+}
+
+fn read_u16(r: &mut impl Read) -> io::Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// checks the tests of both file formats share, bytecode here and snapshots in `vm::snapshot`
+#[cfg(test)]
+pub(crate) mod format_tests {
+    use std::fmt::Debug;
+    use std::io::{self, Cursor};
+
+    /// writes with `write` and reads the bytes back with `read`
+    pub fn round_trip<T>(write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>, read: impl FnOnce(&mut Cursor<Vec<u8>>) -> io::Result<T>) -> T {
+        let mut bytes = vec![];
+        write(&mut bytes).unwrap();
+        read(&mut Cursor::new(bytes)).unwrap()
+    }
+
+    pub fn read_error<T: Debug>(read: impl FnOnce(&mut Cursor<Vec<u8>>) -> io::Result<T>, bytes: &[u8]) -> String {
+        read(&mut Cursor::new(bytes.to_vec())).unwrap_err().to_string()
+    }
+
+    /// `header` is the magic and version of a file `read` accepts, which it has to turn down with either one changed
+    pub fn rejects_wrong_header<T: Debug>(read: impl Fn(&mut Cursor<Vec<u8>>) -> io::Result<T>, header: &[u8], magic_error: &str, version_error: &str) {
+        let mut bytes = header.to_vec();
+        bytes[..4].copy_from_slice(b"CUBX");
+        assert_eq!(read_error(&read, &bytes), magic_error);
+
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        for wrong in [0, version + 1] {
+            let mut bytes = header.to_vec();
+            bytes[4..6].copy_from_slice(&wrong.to_le_bytes());
+            assert_eq!(read_error(&read, &bytes), version_error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::format_tests::{read_error, rejects_wrong_header, round_trip};
+    use crate::lang::compiler::compile_source;
+
+    fn code() -> Code {
+        let source = "R U :square R' U' ;\n>end\nsquare: M2 U M2 U2 M2 ; <\nend:\n";
+        compile_source("square.cubasm", source, &mut vec![]).unwrap()
+    }
+
+    /// the header of a file, up to the sections
+    fn header() -> Vec<u8> {
+        [MAGIC.as_slice(), &VERSION.to_le_bytes(), &START_MARKER.to_le_bytes(), &scheme()].concat()
+    }
+
+    #[test]
+    fn round_trip_keeps_everything() {
+        let code = code();
+        assert!(!code.labels.is_empty() && !code.files.is_empty());
+        assert_eq!(round_trip(|w| write(&code, w, false), read), code);
+    }
+
+    #[test]
+    fn stripped_round_trip_keeps_only_the_movesets() {
+        let code = code();
+        assert_eq!(round_trip(|w| write(&code, w, true), read), Code { movesets: code.movesets, ..Code::default() });
+    }
+
+    #[test]
+    fn writes_the_same_bytes_every_time() {
+        let mut first = vec![];
+        let mut second = vec![];
+        write(&code(), &mut first, false).unwrap();
+        write(&code(), &mut second, false).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn skips_unknown_sections() {
+        let mut bytes = header();
+        bytes.extend([b"NEWS".as_slice(), &1u32.to_le_bytes(), &[7]].concat());
+        bytes.extend([CODE.as_slice(), &2u32.to_le_bytes(), &6u16.to_le_bytes()].concat());
+        assert_eq!(read(&mut bytes.as_slice()).unwrap().movesets, [0xB0, 6]);
+    }
+
+    #[test]
+    fn rejects_wrong_magic_and_version() {
+        rejects_wrong_header(read, &header(), "not cubvm bytecode", "unsupported bytecode version");
+    }
+
+    #[test]
+    fn rejects_odd_length_code() {
+        let mut bytes = header();
+        bytes.extend([CODE.as_slice(), &3u32.to_le_bytes(), &[6, 0, 0]].concat());
+        assert_eq!(read_error(read, &bytes), "code section ends halfway through a moveset");
+    }
+
+    #[test]
+    fn rejects_sections_longer_than_the_file() {
+        let mut bytes = header();
+        bytes.extend([CODE.as_slice(), &u32::MAX.to_le_bytes(), &6u16.to_le_bytes()].concat());
+        assert_eq!(read_error(read, &bytes), "section is cut off");
+    }
+
+    #[test]
+    fn rejects_missing_code() {
+        assert_eq!(read_error(read, &header()), "missing the code section");
+    }
+}
@@ -1,28 +1,112 @@
-use std::{collections::HashMap, fmt::Error, process};
+use std::collections::HashMap;
 
-use super::token::TokenKind;
+use super::diagnostic::{Diagnostic, Span};
+use super::linker::{Object, Relocation};
+use super::token::{Token, TokenKind};
 
-#[derive(Debug)]
+/// where in the source something was written, lines and columns count from 1
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Location {
+    /// index into `Code::files`
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// the location as `file:line:column`, with its file looked up in `files`
+    pub fn position(&self, files: &[String]) -> String {
+        let mut position = match files.get(self.file) {
+            Some(file) => format!("{}:{}", file, self.line),
+            None => format!("line {}", self.line),
+        };
+        if self.column > 0 {
+            position += &format!(":{}", self.column);
+        }
+        position
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Code {
     pub movesets: Vec<u16>,
+    // where each label was defined
+    pub labels: HashMap<String, u16>,
+    /// the token each moveset came from, line 0 for the start marker
+    pub locations: Vec<Location>,
+    /// the source files the locations point into
+    pub files: Vec<String>,
+}
+
+impl Code {
+    /// the source location of the moveset at the offset, if it has one
+    pub fn location(&self, offset: usize) -> Option<Location> {
+        self.locations.get(offset).copied().filter(|l| l.line > 0)
+    }
+
+    /// the source location of the moveset at the offset as `file:line:column`
+    pub fn position(&self, offset: usize) -> Option<String> {
+        Some(self.location(offset)?.position(&self.files))
+    }
+
+    /// the label the offset falls under, with its definition
+    pub fn label_at(&self, offset: usize) -> Option<(&str, usize)> {
+        self.labels.iter()
+            .map(|(name, target)| (name.as_str(), usize::from(*target)))
+            .filter(|(_, target)| *target <= offset)
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+    }
+}
+
+/// the notation of every move, indexed by its moveset
+const MOVE_NAMES: [&str; 28] = [
+    "U", "U'", "U2", "F", "F'", "F2", "R", "R'", "R2",
+    "B", "B'", "B2", "L", "L'", "L2", "D", "D'", "D2",
+    "X", "X'", "X2", "Y", "Y'", "Y2", "Z", "Z'", "Z2",
+    "*",
+];
+
+/// how a moveset is written in cubasm, jumps without their label
+pub fn moveset_name(moveset: u16) -> Option<&'static str> {
+    match moveset {
+        0..=27 => Some(MOVE_NAMES[moveset as usize]),
+        0x002C => Some(","),
+        0x003A => Some(":"),
+        0x003B => Some(";"),
+        0x003C => Some("<"),
+        0x003D => Some("="),
+        0x003E => Some(">"),
+        _ => None,
+    }
 }
 
-pub fn generate(tokens: Vec<TokenKind>) -> Result<Code, Error> {
+/// generates the code of one unit, jumps to imported labels are left for the linker.
+///
+/// labels are defined with `name:` and can be jumped to before their definition,
+/// jump operands are filled in after all of the code is generated.
+///
+/// `files` are the files the tokens came from, the unit's file and the ones it includes.
+/// problems go to `diagnostics`, there is no object if any of them is an error
+pub fn generate(tokens: Vec<Token>, files: Vec<String>, diagnostics: &mut Vec<Diagnostic>) -> Option<Object> {
 
-    let mut line_count: usize = 1;
+    let mut failed = false;
 
-    let mut label_map: HashMap<TokenKind, u16> = HashMap::new();
+    let mut label_map: HashMap<String, u16> = HashMap::new();
+    let mut exports: Vec<(String, Span)> = vec![];
+    let mut imports: Vec<String> = vec![];
+    let mut relocations: Vec<Relocation> = vec![];
+    // where each label was defined
+    let mut definitions: HashMap<String, Span> = HashMap::new();
+    // the jump operands, with the label they target
+    let mut references: Vec<(usize, String, Span)> = vec![];
 
     let mut buf: Vec<u16> = vec![0xB0u16]; // SOF written above
+    let mut locations: Vec<Location> = vec![Location::default()];
     let mut i: usize = 1;
     while i < tokens.len()-1 { // saves reading past EOF
-        if tokens[i] == TokenKind::Newline {
-            line_count += 1;
-        }
-
         // simple optimization by skipping moves that cancel each other out
-        if tokens[i+1] != tokens[i].opposite() && tokens[i].opposite() != tokens[i-1] {
-            match tokens[i] {
+        if tokens[i+1].kind != tokens[i].kind.opposite() && tokens[i].kind.opposite() != tokens[i-1].kind {
+            match tokens[i].kind {
                 // check token.rs
                 TokenKind::U => buf.push(0),
                 TokenKind::Uprime => buf.push(1),
@@ -55,16 +139,16 @@ pub fn generate(tokens: Vec<TokenKind>) -> Result<Code, Error> {
 
                 TokenKind::Asterisk => buf.push(27),
 
-                // slice moves are sort of macros
+                // slice moves are sort of macros, the outer layers turn against the slice and the whole cube turns with it
                 TokenKind::M => {
                     buf.push(13); // L'
                     buf.push(6);  // R
-                    buf.push(18); // X
+                    buf.push(19); // X'
                 }
                 TokenKind::Mprime => {
                     buf.push(12); // L
                     buf.push(7);  // R'
-                    buf.push(19); // X'
+                    buf.push(18); // X
                 }
                 TokenKind::M2 => {
                     buf.push(14); // L2
@@ -72,15 +156,15 @@ pub fn generate(tokens: Vec<TokenKind>) -> Result<Code, Error> {
                     buf.push(20); // X2
                 }
                 TokenKind::S => {
-                    buf.push(3);  // F
-                    buf.push(10); // B'
-                    buf.push(25); // Z'
-                }
-                TokenKind::Sprime => {
                     buf.push(4);  // F'
                     buf.push(9);  // B
                     buf.push(24); // Z
                 }
+                TokenKind::Sprime => {
+                    buf.push(3);  // F
+                    buf.push(10); // B'
+                    buf.push(25); // Z'
+                }
                 TokenKind::S2 => {
                     buf.push(5);  // F2
                     buf.push(11); // B2
@@ -105,45 +189,168 @@ pub fn generate(tokens: Vec<TokenKind>) -> Result<Code, Error> {
                 TokenKind::Semicolon => buf.push(b';'.into()),
                 TokenKind::Comma => buf.push(b','.into()),
 
-                TokenKind::JumpLabel(_) => {
+                TokenKind::Return => buf.push(b'<'.into()),
 
-                    if label_map.contains_key(&tokens[i]) {
-                        buf.push(0x003Au16); // insert an ascii : to signal a jump label
-                        buf.push(*label_map.get(&tokens[i]).unwrap())
-                    } else {
-                        // check the unknown label isn't defined in the middle of the line
-                        if ![TokenKind::SOF, TokenKind::Newline].contains(&tokens[i-1]) {
-                            println!("error on line {}: Labels can only be defined at the start of a new line", line_count);
-                            process::exit(1);
+                TokenKind::Label(ref name) => {
+                    let span = Span::new(tokens[i].location, tokens[i].len, &files);
+                    match definitions.get(name) {
+                        Some(first) => {
+                            diagnostics.push(Diagnostic::error(format!("label {} is already defined", name))
+                                .at(span)
+                                .note(format!("first defined at {}:{}:{}", first.file, first.line, first.column)));
+                            failed = true;
+                        }
+                        None => {
+                            // always points to the next instruction
+                            match u16::try_from(buf.len()) {
+                                Ok(target) => { label_map.insert(name.clone(), target); }
+                                Err(_) => {
+                                    diagnostics.push(Diagnostic::error(format!("label {} is at offset {}, which does not fit in 16 bit jump targets", name, buf.len()))
+                                        .at(span.clone()));
+                                    failed = true;
+                                }
+                            }
+                            definitions.insert(name.clone(), span);
                         }
-
-                        label_map.insert(tokens[i].clone(), buf.len().try_into().unwrap());
-                        //buf.push(buf.len().try_into().unwrap()) // always point to the next instruction
                     }
                 }
-                TokenKind::ConditionalLabel(_) => {
-
-                    if label_map.contains_key(&tokens[i]) {
-                        buf.push(0x003Du16); // insert an ascii = to signal a conditional label
-                        buf.push(*label_map.get(&tokens[i]).unwrap())
-                    } else {
-                        // check the unknown label isn't defined in the middle of the line
-                        if ![TokenKind::SOF, TokenKind::Newline].contains(&tokens[i-1]) {
-                            println!("error on line {}: Labels can only be defined at the start of a new line", line_count);
-                            process::exit(1);
-                        }
 
-                        label_map.insert(tokens[i].clone(), buf.len().try_into().unwrap());
-                        //buf.push(buf.len().try_into().unwrap()) // always point to the next instruction
-                    }
+                TokenKind::CallLabel(ref name)
+                | TokenKind::JumpLabel(ref name)
+                | TokenKind::ConditionalLabel(ref name) => {
+                    buf.push(match tokens[i].kind {
+                        TokenKind::CallLabel(_) => b':'.into(), // push the return address and jump
+                        TokenKind::JumpLabel(_) => b'>'.into(), // just jump
+                        _ => b'='.into(),                       // pop and jump if the U face sum > 0
+                    });
+                    // filled in once every label is defined
+                    references.push((buf.len(), name.clone(), Span::new(tokens[i].location, tokens[i].len, &files)));
+                    buf.push(0);
                 }
+                TokenKind::Export(ref name) if !exports.iter().any(|(export, _)| export == name) => {
+                    exports.push((name.clone(), Span::new(tokens[i].location, tokens[i].len, &files)));
+                }
+                TokenKind::Import(ref name) if !imports.contains(name) => imports.push(name.clone()),
                 TokenKind::EOF => break, // should actually panic but this works too
                 _ => {},
             }
         }
 
+        // slices and jumps map every moveset they became back to their token
+        locations.resize(buf.len(), tokens[i].location);
         i += 1; 
     }
 
-    Ok(Code{movesets: buf})
-}
\ No newline at end of file
+    // second pass, every label is known now
+    for (at, name, span) in references {
+        if imports.contains(&name) {
+            relocations.push(Relocation::Import(at, name)); // the linker fills it in
+        } else if let Some(target) = label_map.get(&name) {
+            buf[at] = *target;
+            relocations.push(Relocation::Local(at));
+        } else if !definitions.contains_key(&name) { // labels past the jump targets are reported where they are defined
+            diagnostics.push(Diagnostic::error(format!("label {} is never defined", name))
+                .at(span)
+                .help(format!("define it with `{}:`, or import it from another file with `.import {}`", name, name)));
+            failed = true;
+        }
+    }
+    for name in &imports {
+        if let Some(span) = definitions.get(name) {
+            diagnostics.push(Diagnostic::error(format!("label {} is imported, but also defined here", name)).at(span.clone()));
+            failed = true;
+        }
+    }
+
+    for (name, span) in &exports {
+        if !definitions.contains_key(name) {
+            diagnostics.push(Diagnostic::error(format!("exported label {} is never defined", name)).at(span.clone()));
+            failed = true;
+        }
+    }
+    if failed {
+        return None;
+    }
+
+    Some(Object {
+        code: Code{movesets: buf, labels: label_map, locations, files},
+        exports: exports.into_iter().map(|(name, _)| name).collect(),
+        imports,
+        relocations,
+    })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::compiler::source_object;
+
+    fn object(source: &str) -> Object {
+        source_object("test.cubasm", source.to_string(), &mut vec![]).unwrap()
+    }
+
+    fn errors(source: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        assert!(source_object("test.cubasm", source.to_string(), &mut diagnostics).is_none());
+        diagnostics
+    }
+
+    #[test]
+    fn labels_can_be_used_before_they_are_defined() {
+        let object = object(">end R end: U ; >end");
+        assert_eq!(object.code.movesets, [0xB0, 0x003E, 4, 6, 0, 0x003B, 0x003E, 4]);
+        assert_eq!(object.code.labels, HashMap::from([("end".to_string(), 4)]));
+        assert_eq!(object.relocations, [Relocation::Local(2), Relocation::Local(7)]);
+    }
+
+    #[test]
+    fn imports_are_left_to_the_linker() {
+        let object = object(".import lib\n:lib ;");
+        assert_eq!(object.code.movesets, [0xB0, 0x003A, 0, 0x003B]);
+        assert_eq!(object.relocations, [Relocation::Import(2, "lib".to_string())]);
+    }
+
+    #[test]
+    fn duplicate_labels_point_at_the_first_definition() {
+        let errors = errors("x: R\nU x: ;");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "label x is already defined");
+        assert_eq!(errors[0].span.as_ref().map(|s| (s.line, s.column)), Some((2, 3)));
+        assert_eq!(errors[0].notes, ["first defined at test.cubasm:1:1"]);
+    }
+
+    #[test]
+    fn undefined_labels_are_errors() {
+        let errors = errors("R :nowhere ;");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "label nowhere is never defined");
+        assert_eq!(errors[0].span.as_ref().map(|s| (s.column, s.len)), Some((3, 8)));
+        assert!(errors[0].help.is_some());
+    }
+
+    #[test]
+    fn imported_labels_cannot_be_defined() {
+        let errors = errors(".import x\nx: R >x");
+        let messages: Vec<&str> = errors.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["label x is imported, but also defined here"]);
+    }
+
+    #[test]
+    fn every_label_problem_is_reported() {
+        let errors = errors(".export gone\nx: x: >y =z");
+        let messages: Vec<&str> = errors.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, [
+            "label x is already defined",
+            "label y is never defined",
+            "label z is never defined",
+            "exported label gone is never defined",
+        ]);
+    }
+
+    #[test]
+    fn labels_past_16_bit_jump_targets_are_errors() {
+        let source = "R U ".repeat(35000) + "\nend: R ; >end";
+        let errors = errors(&source);
+        let messages: Vec<&str> = errors.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["label end is at offset 70001, which does not fit in 16 bit jump targets"]);
+    }
+}
@@ -1,25 +1,138 @@
 use std::fs;
-use std::process::exit;
+use std::path::{Path, PathBuf};
 
 use super::codegen::{self, Code};
+use super::diagnostic::{Diagnostic, Span};
+use super::linker::{self, Object};
+use super::macros;
 
-use super::token;
+use super::token::{self, Token, TokenKind};
 
-pub fn compile(path: String) -> Code {
+/// the files read for one unit, and which of them are still being read
+#[derive(Default)]
+struct Includes {
+    /// as they were named, `Location::file` indexes this
+    files: Vec<String>,
+    /// canonical paths of every file read so far
+    read: Vec<PathBuf>,
+    /// canonical paths of the files whose includes are being read, outermost first
+    open: Vec<PathBuf>,
+}
 
-    let source_r = fs::read_to_string(path.clone());
+/// compiles one file and the files it includes into an object unit, which still has to be linked.
+///
+/// problems go to `diagnostics`, there is no object if any of them is an error
+pub fn compile_object(path: String, diagnostics: &mut Vec<Diagnostic>) -> Option<Object> {
 
-    let source = match source_r {
+    let source = match fs::read_to_string(&path) {
         Ok(o) => o,
-        Err(_) => panic!("failed to open file {}", path),
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(format!("failed to read {}: {}", path, e)));
+            return None;
+        }
     };
 
+    source_object(&path, source, diagnostics)
+}
+
+/// compiles source text into an object unit, `path` names it in errors and is where its includes are relative to
+pub(crate) fn source_object(path: &str, source: String, diagnostics: &mut Vec<Diagnostic>) -> Option<Object> {
+
+    let mut includes = Includes::default();
+    let before = diagnostics.len();
+
+    let mut tokens = vec![Token { kind: TokenKind::SOF, location: Default::default(), len: 0 }];
+    read_tokens(path, source, &mut includes, &mut tokens, diagnostics);
+    tokens.push(Token { kind: TokenKind::EOF, location: Default::default(), len: 0 });
+
+    let tokens = macros::expand(tokens, &includes.files, diagnostics);
+
+    // the code generator still gets to report its own errors
+    let object = codegen::generate(tokens, includes.files, diagnostics)?;
+    match diagnostics[before..].iter().any(Diagnostic::is_error) {
+        true => None,
+        false => Some(object),
+    }
+}
+
+/// tokenizes a file into `tokens`, with the files it includes in place of their `.include` lines
+fn read_tokens(path: &str, source: String, includes: &mut Includes, tokens: &mut Vec<Token>, diagnostics: &mut Vec<Diagnostic>) {
+
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let file = includes.files.len();
+    includes.files.push(path.to_string());
+    includes.read.push(canonical.clone());
+    includes.open.push(canonical);
+
     let lines: Vec<String> = source.lines().map(String::from).collect();
 
-    let tokens: Vec<token::TokenKind> = token::tokenize(lines);
+    let (file_tokens, errors) = token::tokenize(lines, file);
+    for e in errors {
+        diagnostics.push(Diagnostic::error(e.message).at(Span::new(e.location, e.len, &includes.files)));
+    }
+
+    for token in file_tokens {
+        let TokenKind::Include(ref included) = token.kind else {
+            if ![TokenKind::SOF, TokenKind::EOF].contains(&token.kind) {
+                tokens.push(token);
+            }
+            continue;
+        };
+
+        // relative to the including file
+        let included = Path::new(path).parent().unwrap_or(Path::new("")).join(included);
+        let included = included.to_string_lossy().into_owned();
+        let at = Span::new(token.location, token.len, &includes.files);
+
+        let (canonical, source) = match fs::canonicalize(&included).and_then(|c| Ok((c, fs::read_to_string(&included)?))) {
+            Ok(o) => o,
+            Err(e) => {
+                diagnostics.push(Diagnostic::error(format!("failed to include {}: {}", included, e)).at(at));
+                continue;
+            }
+        };
+
+        if let Some(start) = includes.open.iter().position(|open| *open == canonical) {
+            let cycle: Vec<String> = includes.open[start..].iter().chain([&canonical]).map(|p| p.display().to_string()).collect();
+            diagnostics.push(Diagnostic::error(format!("{} includes itself", included))
+                .at(at)
+                .note(format!("include cycle {}", cycle.join(" -> "))));
+            continue;
+        }
+
+        // every file is included once, later includes of it are already there
+        if !includes.read.contains(&canonical) {
+            read_tokens(&included, source, includes, tokens, diagnostics);
+        }
+    }
+
+    includes.open.pop();
+}
+
+/// compiles the files and links them into one program, which starts at the first file.
+///
+/// every file is compiled even after one fails, so `diagnostics` has all their problems at once.
+/// there is no program if any of them is an error
+pub fn compile(paths: &[String], diagnostics: &mut Vec<Diagnostic>) -> Option<Code> {
+
+    let objects: Vec<Option<Object>> = paths.iter().map(|path| compile_object(path.clone(), diagnostics)).collect();
+    let objects: Vec<Object> = objects.into_iter().collect::<Option<_>>()?;
+
+    link(objects, diagnostics)
+}
+
+/// compiles source text that was not read from a file into a program, `name` is the file errors point into
+pub fn compile_source(name: &str, source: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<Code> {
+    let object = source_object(name, source.to_string(), diagnostics)?;
+    link(vec![object], diagnostics)
+}
 
-    match codegen::generate(tokens) {
-        Ok(o) => return o,
-        Err(_) => exit(1), // generate will print its errors
+fn link(objects: Vec<Object>, diagnostics: &mut Vec<Diagnostic>) -> Option<Code> {
+    match linker::link(objects) {
+        Ok(o) => Some(o),
+        Err(errors) => {
+            diagnostics.extend(errors.iter().map(|e| Diagnostic::error(e.to_string())));
+            None
+        }
     }
-}
\ No newline at end of file
+}
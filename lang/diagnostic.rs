@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+
+use super::codegen::Location;
+use crate::vm::trace::json_string;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// the program cannot be compiled
+    Error,
+    /// the program compiles, but probably not into what was meant
+    Warning,
+}
+
+/// characters on one line of a source file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    /// how many characters it covers, at least one gets a caret
+    pub len: usize,
+}
+
+impl Span {
+    /// the span of `len` characters from a location, with its file looked up in `files`
+    pub fn new(location: Location, len: usize, files: &[String]) -> Span {
+        Span {
+            file: files.get(location.file).cloned().unwrap_or_default(),
+            line: location.line,
+            column: location.column,
+            len,
+        }
+    }
+}
+
+/// a problem with a program, found while compiling it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// where the problem is, none for problems with the program as a whole
+    pub span: Option<Span>,
+    pub message: String,
+    /// more about the problem, like where something was first defined
+    pub notes: Vec<String>,
+    /// how to fix it
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, span: None, message: message.into(), notes: vec![], help: None }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::error(message) }
+    }
+
+    pub fn at(mut self, span: Span) -> Diagnostic {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// the diagnostic for people, with the line it is about and carets under the span:
+    ///
+    /// ```text
+    /// error: unknown token `Q`
+    ///  --> main.cubasm:1:5
+    ///   |
+    /// 1 | R U Q ;
+    ///   |     ^
+    /// ```
+    ///
+    /// `source` is the text of the span's file, the snippet is left out without it
+    pub fn render(&self, source: Option<&str>) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut out = format!("{}: {}\n", severity, self.message);
+
+        let mut gutter = String::from(" ");
+        if let Some(span) = &self.span {
+            gutter = " ".repeat(span.line.to_string().len());
+            let _ = writeln!(out, "{}--> {}:{}:{}", gutter, span.file, span.line, span.column);
+
+            if let Some(text) = source.and_then(|s| s.lines().nth(span.line.wrapping_sub(1))) {
+                // tabs would throw the carets off
+                let text = text.replace('\t', " ");
+                let _ = writeln!(out, "{} |", gutter);
+                let _ = writeln!(out, "{} | {}", span.line, text);
+                let _ = writeln!(out, "{} | {}{}", gutter, " ".repeat(span.column.saturating_sub(1)), "^".repeat(span.len.max(1)));
+            }
+        }
+
+        for note in &self.notes {
+            let _ = writeln!(out, "{} = note: {}", gutter, note);
+        }
+        if let Some(help) = &self.help {
+            let _ = writeln!(out, "{} = help: {}", gutter, help);
+        }
+        out
+    }
+
+    /// the diagnostic as a single line of JSON, for editors
+    pub fn to_json(&self) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let span = match &self.span {
+            Some(span) => format!(
+                "{{\"file\":{},\"line\":{},\"column\":{},\"len\":{}}}",
+                json_string(&span.file), span.line, span.column, span.len,
+            ),
+            None => "null".to_string(),
+        };
+        let notes: Vec<String> = self.notes.iter().map(|n| json_string(n)).collect();
+        let help = self.help.as_deref().map_or("null".to_string(), json_string);
+        format!(
+            "{{\"severity\":\"{}\",\"message\":{},\"span\":{},\"notes\":[{}],\"help\":{}}}",
+            severity, json_string(&self.message), span, notes.join(","), help,
+        )
+    }
+}
+
+/// renders the diagnostics, reading each file they point into once
+pub fn render_all(diagnostics: &[Diagnostic]) -> String {
+    let mut sources: HashMap<&str, Option<String>> = HashMap::new();
+    let mut out = String::new();
+    for diagnostic in diagnostics {
+        let source = diagnostic.span.as_ref().and_then(|span| {
+            sources.entry(&span.file).or_insert_with(|| fs::read_to_string(&span.file).ok()).as_deref()
+        });
+        out += &diagnostic.render(source);
+        out += "\n";
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(line: usize, column: usize, len: usize) -> Span {
+        Span { file: "main.cubasm".to_string(), line, column, len }
+    }
+
+    #[test]
+    fn render_puts_carets_under_the_span() {
+        let diagnostic = Diagnostic::error("unknown token `Qx`").at(span(2, 5, 2)).help("moves are U, F, R, B, L and D");
+        let expected = "\
+error: unknown token `Qx`
+ --> main.cubasm:2:5
+  |
+2 | R U Qx ;
+  |     ^^
+  = help: moves are U, F, R, B, L and D
+";
+        assert_eq!(diagnostic.render(Some("R U ;\nR U Qx ;\n")), expected);
+    }
+
+    #[test]
+    fn render_lines_tabs_up_and_widens_the_gutter() {
+        let diagnostic = Diagnostic::warning("macro Sune hides the built-in algorithm of the same name").at(span(10, 3, 0)).note("a note");
+        let source = "\n".repeat(9) + "\t\tSune R ;";
+        let expected = "\
+warning: macro Sune hides the built-in algorithm of the same name
+  --> main.cubasm:10:3
+   |
+10 |   Sune R ;
+   |   ^
+   = note: a note
+";
+        assert_eq!(diagnostic.render(Some(&source)), expected);
+    }
+
+    #[test]
+    fn render_without_source_or_span() {
+        let at = Diagnostic::error("label x is never defined").at(span(7, 1, 1));
+        assert_eq!(at.render(None), "error: label x is never defined\n --> main.cubasm:7:1\n");
+        // a line past the end of the source has no snippet either
+        assert_eq!(at.render(Some("R ;")), at.render(None));
+
+        let nowhere = Diagnostic::error("label x is exported, but no file defines it").note("exported by a.cubasm");
+        assert_eq!(nowhere.render(None), "error: label x is exported, but no file defines it\n  = note: exported by a.cubasm\n");
+    }
+
+    #[test]
+    fn to_json_escapes_strings() {
+        let diagnostic = Diagnostic::error("unknown token `\"\\`")
+            .at(Span { file: "dir\\a \"b\".cubasm".to_string(), line: 1, column: 2, len: 3 })
+            .note("line one\nline two\t\u{1}");
+        assert_eq!(
+            diagnostic.to_json(),
+            r#"{"severity":"error","message":"unknown token `\"\\`","span":{"file":"dir\\a \"b\".cubasm","line":1,"column":2,"len":3},"notes":["line one\u000aline two\u0009\u0001"],"help":null}"#,
+        );
+    }
+
+    #[test]
+    fn to_json_without_span() {
+        let diagnostic = Diagnostic::warning("w").help("h");
+        assert_eq!(diagnostic.to_json(), r#"{"severity":"warning","message":"w","span":null,"notes":[],"help":"h"}"#);
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use super::codegen::{moveset_name, Code};
+use crate::vm::cube::{self, Cube};
+use crate::vm::vm::apply_move;
+
+/// the name of every opcode the VM knows, indexed by the U face sum
+const OPCODE_NAMES: [&str; 15] = [
+    "NOP", "PSH", "POP", "MEM", "SMS", "SSM", "RET", "PUTC",
+    "PUTN", "GETC", "GETN", "SYSCALL", "STORE", "EXEC", "HALT",
+];
+
+/// writes verified code back out as cubasm, one instruction per line.
+///
+/// labels come from the code when it has them, jump targets without one get `L<offset>`.
+/// every `;` is followed by a comment with the opcode and immediate it submits,
+/// worked out from fresh cubes as the code reads, a jump into the middle of an instruction can change them
+pub fn disassemble(code: &Code, w: &mut impl Write) -> io::Result<()> {
+    let movesets = &code.movesets;
+
+    let mut labels: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for (name, target) in &code.labels {
+        labels.entry(usize::from(*target)).or_default().push(name.clone());
+    }
+    for names in labels.values_mut() {
+        names.sort();
+    }
+
+    // jumps to a target no label names, in stripped bytecode
+    let mut offset = 1;
+    while offset < movesets.len() {
+        if let (0x003A | 0x003D | 0x003E, Some(target)) = (movesets[offset], movesets.get(offset + 1)) {
+            labels.entry(usize::from(*target)).or_insert_with(|| vec![format!("L{}", target)]);
+            offset += 1;
+        }
+        offset += 1;
+    }
+
+    let mut line: Vec<String> = vec![];
+    let mut current = Cube::new();
+    let mut immediate = Cube::new();
+
+    let mut offset = 1;
+    while offset <= movesets.len() {
+        // labels are defined at the start of a line
+        if let Some(names) = labels.get(&offset) {
+            if !line.is_empty() {
+                writeln!(w, "{}", line.join(" "))?;
+                line.clear();
+            }
+            for name in names {
+                writeln!(w, "{}:", name)?;
+            }
+        }
+
+        let Some(&moveset) = movesets.get(offset) else {
+            break;
+        };
+        offset += 1;
+
+        match moveset {
+            0..=27 => {
+                apply_move(&mut current, moveset);
+                line.push(moveset_name(moveset).unwrap().to_string());
+            }
+            0x002C => {
+                immediate = current;
+                current = Cube::new();
+                line.push(",".to_string());
+            }
+            0x003B => {
+                let opcode = current.sum_face(cube::U);
+                let name = OPCODE_NAMES.get(usize::from(opcode)).copied().unwrap_or("NOP");
+                line.push(";".to_string());
+                line.push(format!("// {} {}, immediate {}", opcode, name, immediate.sum_face(cube::U)));
+                writeln!(w, "{}", line.join(" "))?;
+                line.clear();
+
+                current = Cube::new();
+                immediate = Cube::new();
+            }
+            0x003A | 0x003D | 0x003E => {
+                let target = movesets.get(offset).map(|t| usize::from(*t));
+                offset += 1;
+
+                let name = match target.and_then(|t| labels.get(&t)) {
+                    Some(names) => names[0].clone(),
+                    None => "?".to_string(), // missing operand
+                };
+                line.push(format!("{}{}", moveset_name(moveset).unwrap(), name));
+
+                // nothing after an unconditional jump runs unless it is jumped to
+                if moveset == 0x003E {
+                    writeln!(w, "{}", line.join(" "))?;
+                    line.clear();
+                }
+            }
+            0x003C => {
+                line.push("<".to_string());
+                writeln!(w, "{}", line.join(" "))?;
+                line.clear();
+            }
+            _ => line.push(format!("{:#06x}", moveset)), // not valid cubasm, verify rejects it
+        }
+    }
+
+    if !line.is_empty() {
+        writeln!(w, "{}", line.join(" "))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use super::codegen::{Code, Location};
+
+/// one compiled file, with jumps the linker still has to fix up
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    /// the code as if it ran on its own, jumps to imports target 0
+    pub code: Code,
+    /// the labels other units can jump to
+    pub exports: Vec<String>,
+    /// the labels this unit jumps to in other units
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// a jump operand at an offset of its unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Relocation {
+    /// targets this unit, moves along with it
+    Local(usize),
+    /// targets the label another unit exports
+    Import(usize, String),
+}
+
+/// why the units could not be linked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// no unit exports the label, with the file that imports it
+    UndefinedLabel { name: String, file: String },
+    /// two files export the same label
+    DuplicateExport { name: String, first: String, second: String },
+    /// more movesets than jumps can address
+    CodeTooLarge(usize),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::UndefinedLabel { name, file } => write!(f, "{} imports {}, which no file exports", file, name),
+            LinkError::DuplicateExport { name, first, second } => write!(f, "{} is exported by both {} and {}", name, first, second),
+            LinkError::CodeTooLarge(len) => write!(f, "{} movesets do not fit in 16 bit jump targets", len),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// merges the units into one program, the first one is where it starts.
+///
+/// every unit but the last is followed by a jump to the end, so running off the end of one still finishes the program.
+/// labels keep their names where they are free and get the file stem in front, `lib.loop`, where they are not,
+/// then a number after that, `lib.loop.2`, until the name is free.
+/// every problem is returned, not just the first
+pub fn link(objects: Vec<Object>) -> Result<Code, Vec<LinkError>> {
+    let file = |object: &Object| object.code.files.first().cloned().unwrap_or_default();
+
+    // where each unit's offset 0 lands, its start marker is left out
+    let mut bases = vec![];
+    let mut len = 1;
+    for (i, object) in objects.iter().enumerate() {
+        bases.push(len - 1);
+        len += object.code.movesets.len() - 1;
+        if i + 1 < objects.len() {
+            len += 2;
+        }
+    }
+    if len > usize::from(u16::MAX) {
+        return Err(vec![LinkError::CodeTooLarge(len)]);
+    }
+
+    let mut errors = vec![];
+    let mut exports: HashMap<&str, (u16, String)> = HashMap::new();
+    for (object, base) in objects.iter().zip(&bases) {
+        for name in &object.exports {
+            let target = object.code.labels[name] + *base as u16;
+            // the first export is the one jumped to
+            match exports.get(name.as_str()) {
+                Some((_, first)) => errors.push(LinkError::DuplicateExport { name: name.clone(), first: first.clone(), second: file(object) }),
+                None => { exports.insert(name, (target, file(object))); }
+            }
+        }
+    }
+
+    let mut code = Code { movesets: vec![0xB0], locations: vec![Location::default()], ..Code::default() };
+
+    for (i, (object, base)) in objects.iter().zip(&bases).enumerate() {
+        let mut movesets = object.code.movesets.clone();
+        for relocation in &object.relocations {
+            match relocation {
+                Relocation::Local(at) => movesets[*at] += *base as u16,
+                Relocation::Import(at, name) => match exports.get(name.as_str()) {
+                    Some((target, _)) => movesets[*at] = *target,
+                    None => errors.push(LinkError::UndefinedLabel { name: name.clone(), file: file(object) }),
+                },
+            }
+        }
+        code.movesets.extend_from_slice(&movesets[1..]);
+
+        let files = code.files.len();
+        code.locations.extend(object.code.locations[1..].iter().map(|l| Location { file: l.file + files, ..*l }));
+        code.files.extend(object.code.files.iter().cloned());
+
+        let stem = Path::new(&file(object)).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut labels: Vec<_> = object.code.labels.iter().collect();
+        labels.sort_by_key(|(name, _)| (!object.exports.contains(name), *name));
+        for (name, target) in labels {
+            let name = free_name(&code.labels, &stem, name);
+            code.labels.insert(name, target + *base as u16);
+        }
+
+        if i + 1 < objects.len() {
+            code.movesets.extend([0x003E, len as u16]);
+            code.locations.resize(code.movesets.len(), Location::default());
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(code),
+        false => Err(errors),
+    }
+}
+
+/// `name` if no label has it yet, otherwise the first free one of `stem.name`, `stem.name.2`, `stem.name.3`...
+fn free_name(labels: &HashMap<String, u16>, stem: &str, name: &str) -> String {
+    if !labels.contains_key(name) {
+        return name.to_string();
+    }
+    let qualified = format!("{}.{}", stem, name);
+    (1..)
+        .map(|n| match n {
+            1 => qualified.clone(),
+            _ => format!("{}.{}", qualified, n),
+        })
+        .find(|candidate| !labels.contains_key(candidate))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::compiler::source_object;
+
+    fn object(path: &str, source: &str) -> Object {
+        source_object(path, source.to_string(), &mut vec![]).unwrap()
+    }
+
+    #[test]
+    fn relocates_jumps_between_units() {
+        let main = object("main.cubasm", ".import square\nR :square ;\n");
+        let lib = object("lib.cubasm", ".export square\nloop: R >loop\nsquare: U ; <\n");
+        let code = link(vec![main, lib]).unwrap();
+
+        // main is offsets 1 to 4, its jump to the end 5 and 6, lib is offset 0 of its unit at 6
+        assert_eq!(code.movesets.len(), 13);
+        assert_eq!(code.movesets[2..4], [0x003A, 10]);
+        assert_eq!(code.movesets[5..7], [0x003E, 13]);
+        assert_eq!(code.movesets[8..10], [0x003E, 7]);
+        assert_eq!(code.labels, HashMap::from([("square".to_string(), 10), ("loop".to_string(), 7)]));
+        assert_eq!(code.locations.len(), code.movesets.len());
+    }
+
+    #[test]
+    fn renames_clashing_labels_until_they_are_free() {
+        let units = vec![
+            object("main.cubasm", "x: R ;\n"),
+            object("a/lib.cubasm", "x: R ;\n"),
+            object("b/lib.cubasm", "R x: R ;\n"),
+            object("c/other.cubasm", "lib.x: R ; R ;\n"),
+        ];
+        let code = link(units).unwrap();
+
+        let expected = [("x", 1), ("lib.x", 5), ("lib.x.2", 10), ("other.lib.x", 14)];
+        assert_eq!(code.labels, expected.iter().map(|(name, target)| (name.to_string(), *target)).collect());
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let a = object("a.cubasm", ".import missing\n.export x\n>missing x: R ;\n");
+        let b = object("b.cubasm", ".export x\nx: R ;\n");
+        assert_eq!(link(vec![a, b]).unwrap_err(), [
+            LinkError::DuplicateExport { name: "x".to_string(), first: "a.cubasm".to_string(), second: "b.cubasm".to_string() },
+            LinkError::UndefinedLabel { name: "missing".to_string(), file: "a.cubasm".to_string() },
+        ]);
+    }
+}
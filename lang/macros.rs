@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use super::algorithms;
+use super::diagnostic::{Diagnostic, Span};
+use super::token::{Token, TokenKind};
+
+/// a `.macro`, `arity` is the highest `$n` in its body
+struct Macro {
+    body: Vec<Token>,
+    arity: usize,
+    /// the `.macro` that defined it
+    span: Span,
+}
+
+/// what expanding macros works with
+struct Expander<'a> {
+    macros: HashMap<String, Macro>,
+    /// the macros being expanded, outermost first
+    stack: Vec<String>,
+    files: &'a [String],
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+/// takes the `.macro` definitions out of the tokens and replaces every `@name` with the body of its macro,
+/// or with the moves of the built-in algorithm of that name.
+///
+/// the tokens of an expansion are located at the `@name` they came from, arguments keep their own location.
+/// problems go to `diagnostics`, the definition or expansion they are in is left out
+pub fn expand(tokens: Vec<Token>, files: &[String], diagnostics: &mut Vec<Diagnostic>) -> Vec<Token> {
+    let mut expander = Expander { macros: HashMap::new(), stack: vec![], files, diagnostics };
+    let mut out = vec![];
+
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token.kind {
+            TokenKind::Macro(ref name) => {
+                let span = expander.span(&token);
+                let mut body = vec![];
+                loop {
+                    // the next .macro or the end is left for the loop above
+                    match tokens.next_if(|t| !matches!(t.kind, TokenKind::Macro(_) | TokenKind::EOF)) {
+                        Some(Token { kind: TokenKind::EndMacro, .. }) => break,
+                        Some(Token { kind: TokenKind::Newline, .. }) => {} // a macro is one line of moves
+                        Some(inner) => body.push(inner),
+                        None => {
+                            let error = Diagnostic::error(format!("macro {} has no .endm", name))
+                                .at(span.clone())
+                                .help("end every macro with .endm before the next one");
+                            expander.diagnostics.push(error);
+                            break;
+                        }
+                    }
+                }
+
+                if let Some(first) = expander.macros.get(name) {
+                    let note = format!("first defined at {}:{}:{}", first.span.file, first.span.line, first.span.column);
+                    expander.diagnostics.push(Diagnostic::error(format!("macro {} is already defined", name)).at(span).note(note));
+                    continue;
+                }
+                if algorithms::lookup(name).is_some() {
+                    let warning = Diagnostic::warning(format!("macro {} hides the built-in algorithm of the same name", name))
+                        .at(span.clone())
+                        .help("rename the macro to keep using the algorithm");
+                    expander.diagnostics.push(warning);
+                }
+                let arity = body.iter().filter_map(|t| match t.kind { TokenKind::Param(n) => Some(n), _ => None }).max().unwrap_or(0);
+                expander.macros.insert(name.clone(), Macro { body, arity, span });
+            }
+            TokenKind::EndMacro => expander.error(&token, ".endm without a .macro"),
+            TokenKind::Param(_) => expander.error(&token, "macro parameters can only be used in a macro"),
+            TokenKind::Invoke(ref name) => {
+                let Some(arity) = expander.arity(name, &token) else { continue };
+                let mut args = vec![];
+                while args.len() < arity {
+                    match tokens.next_if(|t| !matches!(t.kind, TokenKind::Newline | TokenKind::EOF)) {
+                        Some(arg) => args.push(arg),
+                        None => break,
+                    }
+                }
+                if args.len() < arity {
+                    expander.error(&token, &format!("macro {} takes {} arguments but got {}", name, arity, args.len()));
+                    continue;
+                }
+                expander.invoke(name, args, &token, &mut out);
+            }
+            _ => out.push(token),
+        }
+    }
+
+    out
+}
+
+/// what `$n` can stand for: a move, or a label or a jump to one
+fn is_argument(kind: &TokenKind) -> bool {
+    use TokenKind::*;
+    !matches!(kind, Semicolon | Comma | Return | Export(_) | Import(_) | Include(_) | Macro(_) | EndMacro | Invoke(_) | Param(_) | Newline | Unused | SOF | EOF)
+}
+
+impl Expander<'_> {
+    fn span(&self, token: &Token) -> Span {
+        Span::new(token.location, token.len, self.files)
+    }
+
+    fn error(&mut self, at: &Token, message: &str) {
+        let error = Diagnostic::error(message).at(self.span(at));
+        self.diagnostics.push(error);
+    }
+
+    /// how many arguments a macro takes, built-in algorithms take none
+    fn arity(&mut self, name: &str, call: &Token) -> Option<usize> {
+        match self.macros.get(name) {
+            Some(m) => Some(m.arity),
+            None if algorithms::lookup(name).is_some() => Some(0),
+            None => {
+                let error = Diagnostic::error(format!("no macro or algorithm named {}", name))
+                    .at(self.span(call))
+                    .help("macros have to be defined before they are used");
+                self.diagnostics.push(error);
+                None
+            }
+        }
+    }
+
+    /// writes the body of a macro to `out`, `call` is the `@name` of the outermost expansion
+    fn invoke(&mut self, name: &str, args: Vec<Token>, call: &Token, out: &mut Vec<Token>) {
+        let at = call.location;
+        // macros of the program come first
+        if !self.macros.contains_key(name) {
+            if let Some(moves) = algorithms::lookup(name) {
+                out.extend(moves.into_iter().map(|kind| Token { kind, location: at, len: call.len }));
+            }
+            return;
+        }
+
+        if let Some(start) = self.stack.iter().position(|m| m == name) {
+            let cycle: Vec<&str> = self.stack[start..].iter().map(String::as_str).chain([name]).collect();
+            let error = Diagnostic::error(format!("macro {} expands to itself", name))
+                .at(self.span(call))
+                .note(format!("expanded as {}", cycle.join(" -> ")));
+            self.diagnostics.push(error);
+            return;
+        }
+
+        let mut bad = false;
+        for (i, arg) in args.iter().enumerate() {
+            if !is_argument(&arg.kind) {
+                self.error(arg, &format!("argument {} of macro {} has to be a move or a label", i + 1, name));
+                bad = true;
+            }
+        }
+        if bad {
+            return;
+        }
+
+        // with the arguments filled in
+        let body: Vec<Token> = self.macros[name].body.iter()
+            .map(|t| match t.kind {
+                TokenKind::Param(n) => args[n - 1].clone(),
+                _ => Token { kind: t.kind.clone(), location: at, len: call.len },
+            })
+            .collect();
+
+        self.stack.push(name.to_string());
+        let mut body = body.into_iter();
+        while let Some(token) = body.next() {
+            match token.kind {
+                TokenKind::Invoke(ref inner) => {
+                    let Some(arity) = self.arity(inner, call) else { continue };
+                    let args: Vec<Token> = body.by_ref().take(arity).collect();
+                    if args.len() < arity {
+                        self.error(call, &format!("macro {} takes {} arguments but got {}", inner, arity, args.len()));
+                        continue;
+                    }
+                    self.invoke(inner, args, call, out);
+                }
+                _ => out.push(token),
+            }
+        }
+        self.stack.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lang::compiler::compile_source;
+
+    fn movesets(source: &str) -> Vec<u16> {
+        compile_source("test.cubasm", source, &mut vec![]).unwrap().movesets
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        let mut diagnostics = vec![];
+        assert!(compile_source("test.cubasm", source, &mut diagnostics).is_none());
+        diagnostics.into_iter().map(|d| d.message).collect()
+    }
+
+    #[test]
+    fn parameters_are_replaced_by_the_arguments() {
+        assert_eq!(movesets(".macro push $1 , F D F' ; .endm\n@push R"), movesets("R , F D F' ;"));
+        assert_eq!(movesets(".macro swap $2 $1 .endm\n@swap R U ;"), movesets("U R ;"));
+    }
+
+    #[test]
+    fn labels_and_jumps_can_be_arguments() {
+        assert_eq!(movesets(".macro loop $1 R $2 .endm\n@loop top: >top"), movesets("top: R >top"));
+    }
+
+    #[test]
+    fn macros_expand_inside_macros() {
+        let source = ".macro a R $1 .endm\n.macro b @a $1 U .endm\n@b F @sexy ;";
+        assert_eq!(movesets(source), movesets("R F U R U R' U' ;"));
+    }
+
+    #[test]
+    fn a_macro_cannot_expand_to_itself() {
+        let mut diagnostics = vec![];
+        compile_source("test.cubasm", ".macro a R @b .endm\n.macro b @a .endm\n@a ;", &mut diagnostics);
+        assert_eq!(diagnostics[0].message, "macro a expands to itself");
+        assert_eq!(diagnostics[0].notes, ["expanded as a -> b -> a"]);
+    }
+
+    #[test]
+    fn arguments_are_counted() {
+        assert_eq!(errors(".macro two $1 $2 .endm\n@two R\n"), ["macro two takes 2 arguments but got 1"]);
+        // inside a macro, the arguments run out at the end of its body
+        assert_eq!(errors(".macro two $1 $2 .endm\n.macro one @two $1 .endm\n@one R"), ["macro two takes 2 arguments but got 1"]);
+    }
+
+    #[test]
+    fn only_moves_and_labels_are_arguments() {
+        for argument in [";", ",", "<", "@sexy"] {
+            assert_eq!(errors(&format!(".macro m $1 .endm\n@m {} R", argument)), ["argument 1 of macro m has to be a move or a label"]);
+        }
+    }
+
+    #[test]
+    fn macros_are_defined_once_and_before_use() {
+        assert_eq!(errors("@m\n.macro m R .endm"), ["no macro or algorithm named m"]);
+        assert_eq!(errors(".macro m R .endm\n.macro m U .endm"), ["macro m is already defined"]);
+    }
+}
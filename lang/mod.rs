@@ -1,3 +1,9 @@
+mod algorithms;
+mod macros;
 mod token;
+pub mod bytecode;
 pub mod codegen;
-pub mod compiler;
\ No newline at end of file
+pub mod compiler;
+pub mod diagnostic;
+pub mod disasm;
+pub mod linker;
\ No newline at end of file
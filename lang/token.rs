@@ -1,5 +1,17 @@
 
 
+use super::codegen::Location;
+
+/// a token and where it was written
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub location: Location,
+    /// how many characters it spans
+    pub len: usize,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum TokenKind {
     U, Uprime, U2, // 0, 1, 2
@@ -22,85 +34,182 @@ pub enum TokenKind {
     // NOTE optionally add wide moves
 
     Semicolon,
-    JumpLabel(String),
-    ConditionalLabel(String),
+    Label(String),            // label:
+    CallLabel(String),        // :label
+    JumpLabel(String),        // >label
+    ConditionalLabel(String), // =label
+    Return,                   // <
     Comma,
 
+    Export(String),  // .export label
+    Import(String),  // .import label
+    Include(String), // .include "path"
+    Macro(String),   // .macro name
+    EndMacro,        // .endm
+    Invoke(String),  // @name
+    Param(usize),    // $1
+
     Newline,
     Unused,
     SOF,
     EOF,
 }
 
-pub fn tokenize(lines: Vec<String>) -> Vec<TokenKind> {
+/// a part of the source the lexer could not make sense of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub location: Location,
+    /// how many characters it spans
+    pub len: usize,
+    pub message: String,
+}
 
-    let mut tokens = vec![TokenKind::SOF];
+/// characters that end the word before them and are tokens on their own
+fn is_punctuation(c: char) -> bool {
+    matches!(c, ';' | ',' | '<')
+}
 
-    for line in lines {
+/// splits the lines of a file into tokens, `file` is its index in the list of files the tokens point into.
+///
+/// tokens are separated by any amount of spaces and tabs, `;` `,` and `<` also end the token before them.
+/// every token that is not known is an error, they are left out of the tokens and returned next to them
+pub fn tokenize(lines: Vec<String>, file: usize) -> (Vec<Token>, Vec<LexError>) {
 
-        'parts: for token_part in line.split(' ') {
+    let mut tokens = vec![Token { kind: TokenKind::SOF, location: Location::default(), len: 0 }];
+    let mut errors = vec![];
 
-            if token_part.starts_with(":") {
-                tokens.push(TokenKind::JumpLabel(token_part.replace(":", "")))
+    for (number, line) in lines.iter().enumerate() {
+
+        let chars: Vec<char> = line.chars().collect();
+        let at = |i: usize| Location { file, line: number + 1, column: i + 1 };
+        let comment = |i: usize| chars[i] == '/' && chars.get(i + 1) == Some(&'/');
+
+        // `.export` and `.import` take the rest of the line as names, `.macro` the word after it
+        let mut directive: Option<fn(String) -> TokenKind> = None;
+        let mut naming_macro: Option<usize> = None;
+
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i].is_whitespace() {
+                i += 1;
+                continue;
             }
-            if token_part.starts_with("=") {
-                tokens.push(TokenKind::ConditionalLabel(token_part.replace("=", "")))
+            if comment(i) {
+                break; // continue on the next line
             }
 
-            match token_part {
-                "U" => tokens.push(TokenKind::U),
-                "U'" | "Up" => tokens.push(TokenKind::Uprime),
-                "U2" => tokens.push(TokenKind::U2),
-                "F" => tokens.push(TokenKind::F),
-                "F'" | "Fp" => tokens.push(TokenKind::Fprime),
-                "F2" => tokens.push(TokenKind::F2),
-                "R" => tokens.push(TokenKind::R),
-                "R'" | "Rp" => tokens.push(TokenKind::Rprime),
-                "R2" => tokens.push(TokenKind::R2),
-                "B" => tokens.push(TokenKind::B),
-                "B'" | "Bp" => tokens.push(TokenKind::Bprime),
-                "B2" => tokens.push(TokenKind::B2),
-                "L" => tokens.push(TokenKind::L),
-                "L'" | "Lp" => tokens.push(TokenKind::Lprime),
-                "L2" => tokens.push(TokenKind::L2),
-                "D" => tokens.push(TokenKind::D),
-                "D'" | "Dp" => tokens.push(TokenKind::Dprime),
-                "D2" => tokens.push(TokenKind::D2),
-                // rotations
-                "X" => tokens.push(TokenKind::X),
-                "X'" | "Xp" => tokens.push(TokenKind::Xprime),
-                "X2" => tokens.push(TokenKind::X2),
-                "Y" => tokens.push(TokenKind::Y),
-                "Y'" | "Yp" => tokens.push(TokenKind::Yprime),
-                "Y2" => tokens.push(TokenKind::Y2),
-                "Z" => tokens.push(TokenKind::Z),
-                "Z'" | "Zp" => tokens.push(TokenKind::Zprime),
-                "Z2" => tokens.push(TokenKind::Z2),
-                "*" => tokens.push(TokenKind::Asterisk),
-                // slices
-                "M" => tokens.push(TokenKind::M),
-                "M'" | "Mp" => tokens.push(TokenKind::Mprime),
-                "M2" => tokens.push(TokenKind::M2),
-                "S" => tokens.push(TokenKind::S),
-                "S'" | "Sp" => tokens.push(TokenKind::Sprime),
-                "S2" => tokens.push(TokenKind::S2),
-                "E" => tokens.push(TokenKind::E),
-                "E'" | "Ep" => tokens.push(TokenKind::Eprime),
-                "E2" => tokens.push(TokenKind::E2),
-
-                "\n" => tokens.push(TokenKind::Newline),
-                ";" => tokens.push(TokenKind::Semicolon),
-                "," => tokens.push(TokenKind::Comma),
-
-                "//" => break 'parts, // continue on the next line
-                _ => continue
+            let start = i;
+            i += 1;
+            if !is_punctuation(chars[start]) {
+                while i < chars.len() && !chars[i].is_whitespace() && !is_punctuation(chars[i]) && !comment(i) {
+                    i += 1;
+                }
             }
+            let text: String = chars[start..i].iter().collect();
+            let mut error = |message: String| errors.push(LexError { location: at(start), len: i - start, message });
+
+            let kind = if let Some(directive) = directive.filter(|_| !is_punctuation(chars[start])) {
+                directive(text)
+            } else if naming_macro.take().is_some() {
+                TokenKind::Macro(text)
+            } else {
+                match text.as_str() {
+                    ".export" => { directive = Some(TokenKind::Export); continue }
+                    ".import" => { directive = Some(TokenKind::Import); continue }
+                    ".macro" => { naming_macro = Some(start); continue }
+                    ".include" => {
+                        // the path is quoted, it can have spaces in it
+                        let rest: String = chars[i..].iter().collect();
+                        let Some(path) = rest.trim().strip_prefix('"').and_then(|r| r.strip_suffix('"')) else {
+                            error("expected a quoted path after .include".to_string());
+                            break;
+                        };
+                        tokens.push(Token { kind: TokenKind::Include(path.to_string()), location: at(start), len: chars.len() - start });
+                        break;
+                    }
+                    _ => match word(&text) {
+                        Some(kind) => kind,
+                        None => {
+                            error(format!("unknown token `{}`", text));
+                            continue;
+                        }
+                    },
+                }
+            };
+
+            tokens.push(Token { kind, location: at(start), len: i - start });
         }
 
+        if let Some(start) = naming_macro {
+            errors.push(LexError { location: at(start), len: ".macro".len(), message: "a macro needs a name".to_string() });
+        }
+
+        // lines() already ate the line ending
+        tokens.push(Token { kind: TokenKind::Newline, location: at(chars.len()), len: 0 });
     }
-    tokens.push(TokenKind::EOF);
+    tokens.push(Token { kind: TokenKind::EOF, location: Location::default(), len: 0 });
+
+    (tokens, errors)
+}
+
+/// the token a word of cubasm stands for
+fn word(word: &str) -> Option<TokenKind> {
+    let label = |prefix: char| word.strip_prefix(prefix).filter(|name| !name.is_empty()).map(String::from);
 
-    return tokens;
+    Some(match word {
+        _ if word.starts_with(':') => TokenKind::CallLabel(label(':')?),
+        _ if word.starts_with('>') => TokenKind::JumpLabel(label('>')?),
+        _ if word.starts_with('=') => TokenKind::ConditionalLabel(label('=')?),
+        _ if word.starts_with('@') => TokenKind::Invoke(label('@')?),
+        _ if word.starts_with('$') => TokenKind::Param(word[1..].parse().ok().filter(|n| *n > 0)?),
+        _ if word.ends_with(':') => TokenKind::Label(word.strip_suffix(':').filter(|name| !name.is_empty()).map(String::from)?),
+
+        "U" => TokenKind::U,
+        "U'" | "Up" => TokenKind::Uprime,
+        "U2" => TokenKind::U2,
+        "F" => TokenKind::F,
+        "F'" | "Fp" => TokenKind::Fprime,
+        "F2" => TokenKind::F2,
+        "R" => TokenKind::R,
+        "R'" | "Rp" => TokenKind::Rprime,
+        "R2" => TokenKind::R2,
+        "B" => TokenKind::B,
+        "B'" | "Bp" => TokenKind::Bprime,
+        "B2" => TokenKind::B2,
+        "L" => TokenKind::L,
+        "L'" | "Lp" => TokenKind::Lprime,
+        "L2" => TokenKind::L2,
+        "D" => TokenKind::D,
+        "D'" | "Dp" => TokenKind::Dprime,
+        "D2" => TokenKind::D2,
+        // rotations
+        "X" => TokenKind::X,
+        "X'" | "Xp" => TokenKind::Xprime,
+        "X2" => TokenKind::X2,
+        "Y" => TokenKind::Y,
+        "Y'" | "Yp" => TokenKind::Yprime,
+        "Y2" => TokenKind::Y2,
+        "Z" => TokenKind::Z,
+        "Z'" | "Zp" => TokenKind::Zprime,
+        "Z2" => TokenKind::Z2,
+        "*" => TokenKind::Asterisk,
+        // slices
+        "M" => TokenKind::M,
+        "M'" | "Mp" => TokenKind::Mprime,
+        "M2" => TokenKind::M2,
+        "S" => TokenKind::S,
+        "S'" | "Sp" => TokenKind::Sprime,
+        "S2" => TokenKind::S2,
+        "E" => TokenKind::E,
+        "E'" | "Ep" => TokenKind::Eprime,
+        "E2" => TokenKind::E2,
+
+        ";" => TokenKind::Semicolon,
+        "," => TokenKind::Comma,
+        "<" => TokenKind::Return,
+        ".endm" => TokenKind::EndMacro,
+        _ => return None,
+    })
 }
 
 impl TokenKind {
@@ -156,4 +265,84 @@ impl TokenKind {
             _ => TokenKind::Unused,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(source: &str) -> (Vec<Token>, Vec<LexError>) {
+        let (tokens, errors) = tokenize(source.lines().map(String::from).collect(), 0);
+        let tokens = tokens.into_iter().filter(|t| !matches!(t.kind, TokenKind::SOF | TokenKind::Newline | TokenKind::EOF)).collect();
+        (tokens, errors)
+    }
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        let (tokens, errors) = lex(source);
+        assert_eq!(errors, [], "{}", source);
+        tokens.into_iter().map(|t| t.kind).collect()
+    }
+
+    /// where each token starts and how long it is
+    fn spans(source: &str) -> Vec<(usize, usize, usize)> {
+        lex(source).0.iter().map(|t| (t.location.line, t.location.column, t.len)).collect()
+    }
+
+    #[test]
+    fn tabs_and_repeated_spaces_separate_tokens() {
+        assert_eq!(kinds("R\t\tU   R'\t U'"), [TokenKind::R, TokenKind::U, TokenKind::Rprime, TokenKind::Uprime]);
+        assert_eq!(spans("R\t\tU   R'"), [(1, 1, 1), (1, 4, 1), (1, 8, 2)]);
+    }
+
+    #[test]
+    fn punctuation_ends_the_word_before_it() {
+        use TokenKind::*;
+        assert_eq!(kinds("R U;"), [R, U, Semicolon]);
+        assert_eq!(kinds("*,D' R';"), [Asterisk, Comma, Dprime, Rprime, Semicolon]);
+        assert_eq!(kinds("F2<"), [F2, Return]);
+        assert_eq!(kinds(">loop;"), [JumpLabel("loop".to_string()), Semicolon]);
+    }
+
+    #[test]
+    fn spans_count_lines_and_columns_from_1() {
+        assert_eq!(spans("R U' ;\n\n  :square ;"), [(1, 1, 1), (1, 3, 2), (1, 6, 1), (3, 3, 7), (3, 11, 1)]);
+    }
+
+    #[test]
+    fn comments_run_to_the_end_of_the_line() {
+        assert_eq!(kinds("R U // R' U'\nF"), [TokenKind::R, TokenKind::U, TokenKind::F]);
+        // a comment ends the word it is written against
+        assert_eq!(kinds("R//U\nU2// x"), [TokenKind::R, TokenKind::U2]);
+        assert_eq!(kinds("// only a comment"), []);
+    }
+
+    #[test]
+    fn labels_and_directives() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("loop: :f >g =h @sexy $2 .endm"),
+            [Label("loop".into()), CallLabel("f".into()), JumpLabel("g".into()), ConditionalLabel("h".into()), Invoke("sexy".into()), Param(2), EndMacro],
+        );
+        assert_eq!(kinds(".export a b\n.import c"), [Export("a".into()), Export("b".into()), Import("c".into())]);
+        assert_eq!(kinds(".include \"lib/a b.cubasm\""), [Include("lib/a b.cubasm".into())]);
+        assert_eq!(kinds(".macro push $1 .endm"), [Macro("push".into()), Param(1), EndMacro]);
+    }
+
+    #[test]
+    fn unknown_tokens_are_errors() {
+        let (tokens, errors) = lex("R Q U\n  Rw;");
+        assert_eq!(tokens.iter().map(|t| t.kind.clone()).collect::<Vec<_>>(), [TokenKind::R, TokenKind::U, TokenKind::Semicolon]);
+        let errors: Vec<_> = errors.iter().map(|e| (e.location.line, e.location.column, e.len, e.message.as_str())).collect();
+        assert_eq!(errors, [(1, 3, 1, "unknown token `Q`"), (2, 3, 2, "unknown token `Rw`")]);
+    }
+
+    #[test]
+    fn malformed_words_are_errors() {
+        for source in [":", ">", "=", "@", "$0", "$x", "R/U"] {
+            let (_, errors) = lex(source);
+            assert_eq!(errors.len(), 1, "{}", source);
+        }
+        assert_eq!(lex(".include lib.cubasm").1[0].message, "expected a quoted path after .include");
+        assert_eq!(lex(".macro").1[0].message, "a macro needs a name");
+    }
+}
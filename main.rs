@@ -1,29 +1,290 @@
-mod vm;
-mod lang;
+use std::{env, fs, process, time::{Duration, Instant}};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use cubvm::lang::bytecode;
+use cubvm::lang::codegen::Code;
+use cubvm::lang::compiler::compile;
+use cubvm::lang::diagnostic::{self, Diagnostic};
+use cubvm::lang::disasm;
+use cubvm::vm::config::VmConfig;
+use cubvm::vm::debugger::Debugger;
+use cubvm::vm::error::VmError;
+use cubvm::vm::limits::VmLimits;
+use cubvm::vm::profiler::Profile;
+use cubvm::vm::snapshot::Snapshot;
+use cubvm::vm::trace;
+use cubvm::vm::verify::verify;
+use cubvm::vm::vm::{Status, VM};
 
-use std::{env, time::Instant};
-use lang::compiler::compile;
-use vm::vm::VM;
+const USAGE: &str = "usage: cubvm build [-o FILE] [--strip] [--message-format FORMAT] <file>...\n       cubvm disasm [--message-format FORMAT] <file>...\n       cubvm [run | debug | resume] [--message-format FORMAT] [--max-steps N] [--max-instructions N] [--timeout MS] [--memory-size N] [--stack-size N] [--trace FILE] [--checkpoint FILE] [--profile FILE] [--folded FILE] <file>...";
 
 fn main() {
-    let mut args = env::args_os();
+    let mut limits = VmLimits::default();
+    let mut timeout = None;
+    let mut config = VmConfig::new();
+    let mut trace_path = None;
+    let mut checkpoint_path = None;
+    let mut profile_path = None;
+    let mut folded_path = None;
+    let mut output_path = None;
+    let mut strip = false;
+    let mut json = false;
+    let mut paths = vec![];
 
-    if args.len() < 1 { 
+    let mut args = env::args().skip(1).peekable();
+    let command = args.next_if(|a| ["build", "disasm", "run", "debug", "resume"].contains(&a.as_str()));
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-steps" => limits.max_steps = Some(number(&arg, args.next())),
+            "--max-instructions" => limits.max_instructions = Some(number(&arg, args.next())),
+            "--timeout" => timeout = Some(Duration::from_millis(number(&arg, args.next()))),
+            "--memory-size" => config = config.memory_size(number(&arg, args.next()) as usize),
+            "--stack-size" => config = config.stack_size(number(&arg, args.next()) as usize),
+            "--trace" => trace_path = Some(value(&arg, args.next())),
+            "--checkpoint" => checkpoint_path = Some(value(&arg, args.next())),
+            "--profile" => profile_path = Some(value(&arg, args.next())),
+            "--folded" => folded_path = Some(value(&arg, args.next())),
+            "-o" | "--output" => output_path = Some(value(&arg, args.next())),
+            "--strip" => strip = true,
+            "--message-format" => json = match value(&arg, args.next()).as_str() {
+                "human" => false,
+                "json" => true,
+                _ => {
+                    println!("--message-format is either human or json");
+                    process::exit(1);
+                }
+            },
+            _ if !arg.starts_with("--") => paths.push(arg),
+            _ => {
+                println!("{}", USAGE);
+                process::exit(1);
+            }
+        }
+    }
+
+    let Some(path) = paths.first().cloned() else {
         println!("you must supply the path to your program as an argument");
         return;
+    };
+    if command.as_deref() == Some("resume") && paths.len() > 1 {
+        println!("{}", USAGE);
+        process::exit(1);
     }
 
-    let start = Instant::now();
+    if command.as_deref() == Some("build") {
+        let start = Instant::now();
+        let code = compile_or_exit(&paths, json);
+        eprintln!("Compiled in {:?}", start.elapsed());
+
+        let output_path = output_path.unwrap_or_else(|| Path::new(&path).with_extension("cubc").to_string_lossy().into_owned());
+        if let Err(e) = bytecode::save(&code, &output_path, strip) {
+            fail(format!("failed to write bytecode to {}: {}", output_path, e), json);
+        }
+        return;
+    }
+
+    if command.as_deref() == Some("disasm") {
+        // no timing, the listing is all that goes to stdout
+        let code = if bytecode::is_bytecode(&path) { load_code(&paths, json) } else { compile_or_exit(&paths, json) };
+        if let Err(e) = verify(&code) {
+            println!("{}", e);
+            process::exit(1);
+        }
+        let mut out = BufWriter::new(io::stdout().lock());
+        if let Err(e) = disasm::disassemble(&code, &mut out).and_then(|_| out.flush()) {
+            println!("failed to write the listing: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
 
-    let code = match args.nth(1) {
-        Some(file) =>  {
-            compile(file.into_string().unwrap())
+    let mut vm = config.build();
+    vm.set_limits(limits);
+
+    let loaded = if command.as_deref() == Some("resume") {
+        match Snapshot::load(&path) {
+            Ok(snapshot) => {
+                // the limits count from where the snapshot was taken
+                limits.max_steps = limits.max_steps.map(|max| snapshot.steps + max);
+                limits.max_instructions = limits.max_instructions.map(|max| snapshot.instructions + max);
+                vm.set_limits(limits);
+                vm.restore(snapshot)
+            }
+            Err(e) => {
+                println!("failed to read snapshot {}: {}", path, e);
+                process::exit(1);
+            }
         }
-        None => return,
+    } else {
+        vm.load(load_code(&paths, json))
     };
 
-    println!("Compiled in {:?}", start.elapsed());
+    if let Err(e) = loaded {
+        println!("{}", e);
+        process::exit(1);
+    }
 
-    let mut vm = VM::new();
-    vm.interpret_code(code);
-}
\ No newline at end of file
+    if command.as_deref() == Some("debug") {
+        Debugger::new(vm, source_lines(&path)).repl();
+        return;
+    }
+
+    let mut profile = (profile_path.is_some() || folded_path.is_some()).then(|| Profile::new(vm.code()));
+
+    // the clock only starts once the program is compiled and loaded
+    if let Some(timeout) = timeout {
+        vm.set_limits(VmLimits { deadline: Some(Instant::now() + timeout), ..limits });
+    }
+
+    let result = match (trace_path, &mut profile) {
+        (Some(trace_path), profile) => {
+            let traced = fs::File::create(&trace_path)
+                .and_then(|file| trace::run_traced(&mut vm, Some(&mut BufWriter::new(file)), profile.as_mut()));
+            match traced {
+                Ok(result) => result,
+                Err(e) => {
+                    println!("failed to write trace to {}: {}", trace_path, e);
+                    process::exit(1);
+                }
+            }
+        }
+        (None, Some(profile)) => trace::run_traced(&mut vm, None::<&mut io::Sink>, Some(profile)).unwrap(),
+        (None, None) => vm.run(),
+    };
+
+    if let Some(profile) = &profile {
+        let source = source_lines(&path);
+        if let Some(profile_path) = profile_path {
+            let written = fs::File::create(&profile_path).and_then(|file| profile.write_report(&mut BufWriter::new(file), &source));
+            if let Err(e) = written {
+                println!("failed to write profile to {}: {}", profile_path, e);
+            }
+        }
+        if let Some(folded_path) = folded_path {
+            let written = fs::File::create(&folded_path).and_then(|file| profile.write_folded(&mut BufWriter::new(file)));
+            if let Err(e) = written {
+                println!("failed to write folded stacks to {}: {}", folded_path, e);
+            }
+        }
+    }
+
+    // keep the state of anything that did not run to the end
+    if let Some(checkpoint_path) = checkpoint_path {
+        if !matches!(result, Ok(Status::Finished | Status::Halted(_))) {
+            match vm.snapshot().save(&checkpoint_path) {
+                Ok(()) => println!("saved the VM state to {}", checkpoint_path),
+                Err(e) => println!("failed to write snapshot to {}: {}", checkpoint_path, e),
+            }
+        }
+    }
+
+    report(&vm, result);
+}
+
+/// compiles and links source files, or reads a bytecode file that was built before
+fn load_code(paths: &[String], json: bool) -> Code {
+    if let Some(path) = paths.iter().find(|path| bytecode::is_bytecode(path)) {
+        if paths.len() > 1 {
+            fail(format!("{} is bytecode, which is already linked and runs on its own", path), json);
+        }
+        return match bytecode::load(path) {
+            Ok(code) => code,
+            Err(e) => fail(format!("failed to read bytecode {}: {}", path, e), json),
+        };
+    }
+
+    let start = Instant::now();
+
+    let code = compile_or_exit(paths, json);
+
+    // stdout is the program's own output
+    eprintln!("Compiled in {:?}", start.elapsed());
+
+    code
+}
+
+/// compiles and links source files, printing every diagnostic, as one JSON object per line with `json`.
+/// exits if there were errors
+fn compile_or_exit(paths: &[String], json: bool) -> Code {
+    let mut diagnostics = vec![];
+    let code = compile(paths, &mut diagnostics);
+
+    if json {
+        for d in &diagnostics {
+            println!("{}", d.to_json());
+        }
+    } else if !diagnostics.is_empty() {
+        print!("{}", diagnostic::render_all(&diagnostics));
+    }
+
+    match code {
+        Some(code) => code,
+        None => {
+            if !json {
+                let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+                println!("could not compile, {} error{}", errors, if errors == 1 { "" } else { "s" });
+            }
+            process::exit(1);
+        }
+    }
+}
+
+/// prints why the program could not be loaded, as a diagnostic with `json`, and exits
+fn fail(message: String, json: bool) -> ! {
+    match json {
+        true => println!("{}", Diagnostic::error(message).to_json()),
+        false => println!("{}", message),
+    }
+    process::exit(1);
+}
+
+/// the lines of a source file, none for bytecode
+fn source_lines(path: &str) -> Vec<String> {
+    if bytecode::is_bytecode(path) {
+        return vec![];
+    }
+    fs::read_to_string(path).unwrap_or_default().lines().map(String::from).collect()
+}
+
+/// prints why the VM stopped and exits with a matching status
+fn report(vm: &VM, result: Result<Status, VmError>) {
+    match result {
+        Ok(Status::LimitReached(limit)) => {
+            println!("stopped at offset {}: {}", vm.program_counter(), limit);
+            process::exit(2);
+        }
+        Ok(Status::Halted(exit_status)) => process::exit(exit_status.into()),
+        Ok(_) => {}
+        Err(e) => {
+            let offset = vm.program_counter() - 1;
+            match (vm.exec_pointer(), vm.code().position(offset)) {
+                (Some(address), _) => println!("error in memory at {}: {}", address, e),
+                (None, Some(position)) => println!("error at offset {} ({}): {}", offset, position, e),
+                (None, None) => println!("error at offset {}: {}", offset, e),
+            }
+            process::exit(1);
+        }
+    }
+}
+
+/// the value following a flag
+fn value(flag: &str, value: Option<String>) -> String {
+    match value {
+        Some(v) => v,
+        None => {
+            println!("{} expects a value", flag);
+            process::exit(1);
+        }
+    }
+}
+
+/// the value following a numeric flag
+fn number(flag: &str, value: Option<String>) -> u64 {
+    match value.map(|v| v.parse()) {
+        Some(Ok(n)) => n,
+        _ => {
+            println!("{} expects a number", flag);
+            process::exit(1);
+        }
+    }
+}
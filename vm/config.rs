@@ -0,0 +1,40 @@
+use crate::vm::vm::VM;
+
+/// the sizes to build a VM with, `VmConfig::new().memory_size(1 << 20).build()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmConfig {
+    pub(crate) memory_size: usize,
+    pub(crate) stack_size: usize,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            memory_size: 8192, // 8K memory
+            stack_size: 128,
+        }
+    }
+}
+
+impl VmConfig {
+    pub fn new() -> VmConfig {
+        VmConfig::default()
+    }
+
+    /// how many memory addresses there are, address 0 included.
+    /// memory is allocated a page at a time as it is written to
+    pub fn memory_size(mut self, cells: usize) -> VmConfig {
+        self.memory_size = cells;
+        self
+    }
+
+    /// how many stack slots there are, the empty slot 0 at the bottom included
+    pub fn stack_size(mut self, slots: usize) -> VmConfig {
+        self.stack_size = slots.max(1);
+        self
+    }
+
+    pub fn build(self) -> VM {
+        VM::with_config(self)
+    }
+}
@@ -1,12 +1,14 @@
-/// standard 3x3 (Rubik's) Cube
-///
-/// layout of the faces array is as follows:
-/// U, F, R, B, L, D,
-///
-/// with the correct facing of the colours as defined by the scrambling rules
-/// set by the World Cubing Association in WCA regulation 4d1.
-///
-/// reference: https://www.worldcubeassociation.org/regulations#4d1
+//! standard 3x3 (Rubik's) Cube
+//!
+//! layout of the faces array is as follows:
+//! U, F, R, B, L, D,
+//!
+//! with the correct facing of the colours as defined by the scrambling rules
+//! set by the World Cubing Association in WCA regulation 4d1.
+//!
+//! reference: https://www.worldcubeassociation.org/regulations#4d1
+
+use std::fmt;
 
 #[allow(unused)]
 pub const U: usize = 0;
@@ -21,7 +23,7 @@ pub const L: usize = 4;
 #[allow(unused)]
 pub const D: usize = 5;
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub struct Cube {
     /// a face is 9 bytes.
     /// layout of a face by indices:
@@ -45,13 +47,53 @@ fn rotate_face_prime(r: &mut[u8; 9]) {
     *r = [r[6], r[3], r[0], r[7], r[4], r[1], r[8], r[5], r[2]];
 }
 
+/// the letter of a sticker's colour, `#` for values that are not a colour
+fn sticker(value: u8) -> char {
+    match value {
+        0x00 => 'W',
+        0x01 => 'G',
+        0x02 => 'R',
+        0x04 => 'B',
+        0x08 => 'O',
+        0x10 => 'Y',
+        _ => '#',
+    }
+}
+
+/// draws the cube as an unfolded net:
+///
+/// ```text
+///       U
+///     L F R B
+///       D
+/// ```
+impl fmt::Display for Cube {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = |face: usize, r: usize| -> String {
+            (0..3).map(|c| sticker(self.faces[face][c * 3 + r]).to_string()).collect::<Vec<_>>().join(" ")
+        };
+
+        for r in 0..3 {
+            writeln!(f, "      {}", row(U, r))?;
+        }
+        for r in 0..3 {
+            writeln!(f, "{} {} {} {}", row(L, r), row(F, r), row(R, r), row(B, r))?;
+        }
+        for r in 0..3 {
+            writeln!(f, "      {}", row(D, r))?;
+        }
+        Ok(())
+    }
+}
+
 // to save time and screen space
 trait Sum {
     fn sum(&self) -> u8;
 }
 impl Sum for [u8; 9] {
     fn sum(&self) -> u8 {
-        return self[0] + self[1] + self[2] + self[3] + self[4] + self[5] + self[6] + self[7] + self[8];
+        // wrapping, stickers written by the VM can hold any value
+        self.iter().fold(0u8, |acc, s| acc.wrapping_add(*s))
     }
 }
 
@@ -70,6 +112,13 @@ impl Cube {
         }
     }
 
+    /// a solved cube with `value` on the U centre, so its U face sums to `value`
+    pub fn with_value(value: u8) -> Cube {
+        let mut cube = Cube::new();
+        cube.faces[U][4] = value;
+        cube
+    }
+
     pub fn sum_face(&self, face: usize) -> u8 {
         match face {
             U => self.faces[U].sum(),
@@ -95,6 +144,9 @@ impl Cube {
         
         rotate_face(&mut self.faces[R]);
         rotate_face_prime(&mut self.faces[L]);
+        // U and B are read upside down from each other, same for B and D
+        self.faces[U].reverse();
+        self.faces[B].reverse();
  
         self.faces = [
             self.faces[F], // F -> U
@@ -112,6 +164,9 @@ impl Cube {
     pub fn rotate_x_prime(&mut self) {
         rotate_face_prime(&mut self.faces[R]);
         rotate_face(&mut self.faces[L]);
+        // B is read upside down from U and D
+        self.faces[B].reverse();
+        self.faces[D].reverse();
  
         self.faces = [
             self.faces[B], // B -> U
@@ -129,6 +184,9 @@ impl Cube {
     pub fn rotate_x2(&mut self) {        
         self.faces[R].reverse();
         self.faces[L].reverse();
+        // F and B are read upside down from each other
+        self.faces[F].reverse();
+        self.faces[B].reverse();
  
         self.faces = [
             self.faces[D], // D -> U
@@ -197,6 +255,10 @@ impl Cube {
     pub fn rotate_z(&mut self) {
         rotate_face(&mut self.faces[F]);
         rotate_face_prime(&mut self.faces[B]);
+        // the faces around F turn along with it
+        for face in [U, R, D, L] {
+            rotate_face(&mut self.faces[face]);
+        }
 
         self.faces = [
             self.faces[L], // L -> U
@@ -215,6 +277,10 @@ impl Cube {
         
         rotate_face_prime(&mut self.faces[F]);
         rotate_face(&mut self.faces[B]);
+        // the faces around F turn along with it
+        for face in [U, R, D, L] {
+            rotate_face_prime(&mut self.faces[face]);
+        }
 
         // change facings
         self.faces = [
@@ -233,6 +299,10 @@ impl Cube {
     pub fn rotate_z2(&mut self) {        
         self.faces[F].reverse();
         self.faces[B].reverse();
+        // the faces around F turn along with it
+        for face in [U, R, D, L] {
+            self.faces[face].reverse();
+        }
  
         self.faces = [
             self.faces[D], // D -> U
@@ -307,7 +377,7 @@ impl Cube {
         self.faces[R] = [u[2], u[5], u[8], r[3], r[4], r[5], r[6], r[7], r[8]];
         self.faces[L] = [l[0], l[1], l[2], l[3], l[4], l[5], d[0], d[3], d[6]];
         self.faces[U] = [u[0], u[1], l[8], u[3], u[4], l[7], u[6], u[7], l[6]];
-        self.faces[D] = [r[0], d[1], d[2], r[1], d[4], d[5], r[2], d[7], d[8]];
+        self.faces[D] = [r[2], d[1], d[2], r[1], d[4], d[5], r[0], d[7], d[8]];
     }
 
     /// twists the F side counter-clockwise (F')
@@ -319,10 +389,10 @@ impl Cube {
 
         rotate_face_prime(&mut self.faces[F]);
 
-        self.faces[R] = [d[0], d[3], d[6], r[3], r[4], r[5], r[6], r[7], r[8]];
-        self.faces[L] = [l[0], l[1], l[2], l[3], l[4], l[5], d[2], d[5], d[8]];
+        self.faces[R] = [d[6], d[3], d[0], r[3], r[4], r[5], r[6], r[7], r[8]];
+        self.faces[L] = [l[0], l[1], l[2], l[3], l[4], l[5], u[8], u[5], u[2]];
         self.faces[U] = [u[0], u[1], r[0], u[3], u[4], r[1], u[6], u[7], r[2]];
-        self.faces[D] = [d[0], d[1], l[6], d[3], d[4], l[7], d[6], d[7], l[8]];
+        self.faces[D] = [l[6], d[1], d[2], l[7], d[4], d[5], l[8], d[7], d[8]];
     }
 
     /// twists the F side twice (F2)
@@ -335,8 +405,8 @@ impl Cube {
         self.faces[F].reverse();
 
         self.faces[R] = [l[8], l[7], l[6], r[3], r[4], r[5], r[6], r[7], r[8]];
-        self.faces[L] = [l[0], l[1], l[2], l[3], l[4], r[5], r[2], l[1], l[0]];
-        self.faces[U] = [u[0], u[1], d[0], u[3], u[4], d[3], u[6], u[7], d[6]];
+        self.faces[L] = [l[0], l[1], l[2], l[3], l[4], l[5], r[2], r[1], r[0]];
+        self.faces[U] = [u[0], u[1], d[6], u[3], u[4], d[3], u[6], u[7], d[0]];
         self.faces[D] = [u[8], d[1], d[2], u[5], d[4], d[5], u[2], d[7], d[8]];
     }
 
@@ -351,8 +421,8 @@ impl Cube {
 
         self.faces[F] = [f[0], f[1], f[2], f[3], f[4], f[5], d[6], d[7], d[8]];
         self.faces[U] = [u[0], u[1], u[2], u[3], u[4], u[5], f[6], f[7], f[8]];
-        self.faces[B] = [u[6], u[7], u[8], b[3], b[4], b[5], b[6], b[7], b[8]];
-        self.faces[D] = [d[0], d[1], d[2], d[3], d[4], d[5], b[0], b[1], b[2]];
+        self.faces[B] = [u[8], u[7], u[6], b[3], b[4], b[5], b[6], b[7], b[8]];
+        self.faces[D] = [d[0], d[1], d[2], d[3], d[4], d[5], b[2], b[1], b[0]];
     }
 
     /// twists the R side counter clockwise (R')
@@ -365,9 +435,9 @@ impl Cube {
         rotate_face_prime(&mut self.faces[R]);
 
         self.faces[F] = [f[0], f[1], f[2], f[3], f[4], f[5], u[6], u[7], u[8]];
-        self.faces[B] = [d[6], d[7], d[8], b[3], b[4], b[5], b[6], b[7], b[8]];
+        self.faces[B] = [d[8], d[7], d[6], b[3], b[4], b[5], b[6], b[7], b[8]];
         self.faces[D] = [d[0], d[1], d[2], d[3], d[4], d[5], f[6], f[7], f[8]];
-        self.faces[U] = [u[0], u[1], u[2], u[3], u[4], u[5], b[0], b[1], b[2]];
+        self.faces[U] = [u[0], u[1], u[2], u[3], u[4], u[5], b[2], b[1], b[0]];
     }
 
     /// twists the R side twice (R2)
@@ -381,9 +451,9 @@ impl Cube {
         self.faces[R].reverse();
         
         self.faces[D] = [d[0], d[1], d[2], d[3], d[4], d[5], u[6], u[7], u[8]];
-        self.faces[U] = [u[0], u[1], u[2], u[3], u[4], u[5], d[0], d[1], d[2]];
-        self.faces[F] = [f[0], f[1], f[2], f[3], f[4], f[5], b[0], u[1], u[2]];
-        self.faces[B] = [f[6], f[7], f[8], b[3], b[4], b[5], b[6], b[7], b[8]];
+        self.faces[U] = [u[0], u[1], u[2], u[3], u[4], u[5], d[6], d[7], d[8]];
+        self.faces[F] = [f[0], f[1], f[2], f[3], f[4], f[5], b[2], b[1], b[0]];
+        self.faces[B] = [f[8], f[7], f[6], b[3], b[4], b[5], b[6], b[7], b[8]];
     }
 
     /// twists the B side clockwise (B)
@@ -413,7 +483,7 @@ impl Cube {
         self.faces[D] = [d[0], d[1], r[8], d[3], d[4], r[7], d[6], d[7], r[6]];
         self.faces[U] = [l[2], u[1], u[2], l[1], u[4], u[5], l[0], u[7], u[8]];
         self.faces[L] = [d[2], d[5], d[8], l[3], l[4], l[5], l[6], l[7], l[8]];
-        self.faces[R] = [r[0], r[1], r[2], r[3], r[4], r[5], u[0], u[2], u[5]];
+        self.faces[R] = [r[0], r[1], r[2], r[3], r[4], r[5], u[0], u[3], u[6]];
     }
 
     /// twists the B side twice (B2)
@@ -425,8 +495,8 @@ impl Cube {
 
         self.faces[B].reverse();
 
-        self.faces[D] = [d[0], d[1], u[0], d[3], d[4], u[3], d[6], d[7], u[6]];
-        self.faces[U] = [d[6], u[1], u[2], d[3], u[4], u[5], d[0], u[7], u[8]];
+        self.faces[D] = [d[0], d[1], u[6], d[3], d[4], u[3], d[6], d[7], u[0]];
+        self.faces[U] = [d[8], u[1], u[2], d[5], u[4], u[5], d[2], u[7], u[8]];
         self.faces[L] = [r[8], r[7], r[6], l[3], l[4], l[5], l[6], l[7], l[8]];
         self.faces[R] = [r[0], r[1], r[2], r[3], r[4], r[5], l[2], l[1], l[0]];
     }
@@ -469,7 +539,7 @@ impl Cube {
         self.faces[L].reverse();
 
         self.faces[U] = [d[0], d[1], d[2], u[3], u[4], u[5], u[6], u[7], u[8]];
-        self.faces[D] = [u[0], u[1], u[2], d[3], d[4], d[5], d[6], d[7], u[8]];
+        self.faces[D] = [u[0], u[1], u[2], d[3], d[4], d[5], d[6], d[7], d[8]];
         self.faces[F] = [b[8], b[7], b[6], f[3], f[4], f[5], f[6], f[7], f[8]];
         self.faces[B] = [b[0], b[1], b[2], b[3], b[4], b[5], f[2], f[1], f[0]];
     }
@@ -515,4 +585,119 @@ impl Cube {
         self.faces[R] = [r[0], r[1], l[2], r[3], r[4], l[5], r[6], r[7], l[8]];
         self.faces[L] = [l[0], l[1], r[2], l[3], l[4], r[5], l[6], l[7], r[8]];
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Move = fn(&mut Cube);
+
+    /// every move as clockwise, prime and double
+    const MOVES: [(&str, Move, Move, Move); 9] = [
+        ("U", Cube::twist_u, Cube::twist_u_prime, Cube::twist_u2),
+        ("F", Cube::twist_f, Cube::twist_f_prime, Cube::twist_f2),
+        ("R", Cube::twist_r, Cube::twist_r_prime, Cube::twist_r2),
+        ("B", Cube::twist_b, Cube::twist_b_prime, Cube::twist_b2),
+        ("L", Cube::twist_l, Cube::twist_l_prime, Cube::twist_l2),
+        ("D", Cube::twist_d, Cube::twist_d_prime, Cube::twist_d2),
+        ("X", Cube::rotate_x, Cube::rotate_x_prime, Cube::rotate_x2),
+        ("Y", Cube::rotate_y, Cube::rotate_y_prime, Cube::rotate_y2),
+        ("Z", Cube::rotate_z, Cube::rotate_z_prime, Cube::rotate_z2),
+    ];
+
+    /// a cube where every sticker is different, so a sticker in the wrong place always shows
+    fn numbered() -> Cube {
+        let mut cube = Cube::new();
+        for (f, face) in cube.faces.iter_mut().enumerate() {
+            for (i, sticker) in face.iter_mut().enumerate() {
+                *sticker = (f * 9 + i) as u8;
+            }
+        }
+        cube
+    }
+
+    fn after(moves: &[Move]) -> Cube {
+        let mut cube = numbered();
+        for m in moves {
+            m(&mut cube);
+        }
+        cube
+    }
+
+    #[test]
+    fn moves_are_undone_by_their_inverse() {
+        for (name, clockwise, prime, double) in MOVES {
+            assert_eq!(after(&[clockwise, prime]), numbered(), "{} {}'", name, name);
+            assert_eq!(after(&[prime, clockwise]), numbered(), "{}' {}", name, name);
+            assert_eq!(after(&[double, double]), numbered(), "{}2 {}2", name, name);
+        }
+    }
+
+    #[test]
+    fn moves_have_order_4() {
+        for (name, clockwise, prime, _) in MOVES {
+            assert_ne!(after(&[clockwise]), numbered(), "{} does nothing", name);
+            assert_eq!(after(&[clockwise; 4]), numbered(), "{} four times", name);
+            assert_eq!(after(&[prime; 4]), numbered(), "{}' four times", name);
+        }
+    }
+
+    #[test]
+    fn double_moves_are_two_moves() {
+        for (name, clockwise, prime, double) in MOVES {
+            assert_eq!(after(&[double]), after(&[clockwise, clockwise]), "{}2", name);
+            assert_eq!(after(&[double]), after(&[prime, prime]), "{}2 as {}' {}'", name, name, name);
+        }
+    }
+
+    #[test]
+    fn turns_only_move_their_own_layer() {
+        let twists = &MOVES[..6];
+        for (i, (name, clockwise, _, _)) in twists.iter().enumerate() {
+            let cube = after(&[*clockwise]);
+            // the opposite face is the one the layer does not touch
+            let opposite = [D, B, L, F, R, U][i];
+            assert_eq!(cube.faces[opposite], numbered().faces[opposite], "{} moves the opposite face", name);
+            assert_eq!(cube.faces[i][4], numbered().faces[i][4], "{} moves its centre", name);
+        }
+    }
+
+    #[test]
+    fn rotations_turn_the_faces_into_each_other() {
+        // x follows R, y follows U and z follows F
+        let cases: [(&str, Vec<Move>, Move); 6] = [
+            ("X U X' = F", vec![Cube::rotate_x, Cube::twist_u, Cube::rotate_x_prime], Cube::twist_f),
+            ("X' U X = B", vec![Cube::rotate_x_prime, Cube::twist_u, Cube::rotate_x], Cube::twist_b),
+            ("Y F Y' = R", vec![Cube::rotate_y, Cube::twist_f, Cube::rotate_y_prime], Cube::twist_r),
+            ("Y' F Y = L", vec![Cube::rotate_y_prime, Cube::twist_f, Cube::rotate_y], Cube::twist_l),
+            ("Z U Z' = L", vec![Cube::rotate_z, Cube::twist_u, Cube::rotate_z_prime], Cube::twist_l),
+            ("Z' U Z = R", vec![Cube::rotate_z_prime, Cube::twist_u, Cube::rotate_z], Cube::twist_r),
+        ];
+        for (name, conjugated, twist) in cases {
+            assert_eq!(after(&conjugated), after(&[twist]), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rotations_keep_the_cube_solved() {
+        for (name, clockwise, prime, double) in &MOVES[6..] {
+            for (m, suffix) in [(clockwise, ""), (prime, "'"), (double, "2")] {
+                let mut cube = Cube::new();
+                m(&mut cube);
+                // every face still has one colour
+                assert!(cube.faces.iter().all(|face| face.iter().all(|s| *s == face[4])), "{}{}", name, suffix);
+            }
+        }
+    }
+
+    #[test]
+    fn r_u_has_order_105() {
+        let mut cube = numbered();
+        for n in 1..=105 {
+            cube.twist_r();
+            cube.twist_u();
+            assert_eq!(cube == numbered(), n == 105, "R U {} times", n);
+        }
+    }
+}
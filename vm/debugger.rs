@@ -0,0 +1,404 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use crate::lang::codegen;
+use crate::vm::cube::{self, Cube};
+use crate::vm::vm::{Status, VM};
+
+/// a memory cell or stack slot to stop on when it changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Memory(usize),
+    /// counted from the bottom of the stack, starting at 1
+    Stack(usize),
+}
+
+impl Watch {
+    fn read(&self, vm: &VM) -> Option<Cube> {
+        match *self {
+            Watch::Memory(address) => vm.memory().get(address),
+            Watch::Stack(slot) => slot.checked_sub(1).and_then(|i| vm.stack().get(i)).copied(),
+        }
+    }
+}
+
+struct Watchpoint {
+    watch: Watch,
+    last: Option<Cube>,
+}
+
+/// the first watched cube that is not what it was last seen as
+fn changed(watchpoints: &[Watchpoint], vm: &VM) -> Option<Watch> {
+    watchpoints.iter().find(|w| w.watch.read(vm) != w.last).map(|w| w.watch)
+}
+
+/// why the debugger handed control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint(Watch),
+    /// the VM stopped by itself: it finished or hit a limit
+    Vm(Status),
+    /// running backwards ran out of journal
+    JournalStart,
+}
+
+/// how many steps the debugger can go back
+const JOURNAL_CAPACITY: usize = 1 << 20;
+
+/// drives a VM through breakpoints, watchpoints and single steps
+pub struct Debugger {
+    vm: VM,
+    source: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+const HELP: &str = "\
+commands:
+  break <offset> | break line <n> | break :<label>   stop before this offset
+  delete <offset>                                    remove a breakpoint
+  watch mem <address> | watch stack <slot>           stop when it changes
+  step [n]                                           execute n moves
+  next [n]                                           execute n `;` submitted instructions
+  continue                                           run to the next breakpoint or watchpoint
+  back [n]                                           undo n moves
+  rcontinue                                          run backwards to the previous breakpoint or watchpoint
+  print current | immediate | stack | mem <address>  draw cubes
+  info                                               pointers, breakpoints and watchpoints
+  save <file>                                        write a snapshot of the VM
+  quit";
+
+impl Debugger {
+    /// `vm` should already have its code loaded, `source` are the lines it was compiled from
+    pub fn new(mut vm: VM, source: Vec<String>) -> Debugger {
+        vm.enable_journal(JOURNAL_CAPACITY);
+        Debugger {
+            vm,
+            source,
+            breakpoints: BTreeSet::new(),
+            watchpoints: vec![],
+        }
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    pub fn add_breakpoint(&mut self, offset: usize) {
+        self.breakpoints.insert(offset);
+    }
+
+    pub fn remove_breakpoint(&mut self, offset: usize) -> bool {
+        self.breakpoints.remove(&offset)
+    }
+
+    pub fn add_watchpoint(&mut self, watch: Watch) {
+        let last = watch.read(&self.vm);
+        self.watchpoints.push(Watchpoint { watch, last });
+    }
+
+    /// turns `12`, `line 3` or `:label` into a bytecode offset
+    pub fn resolve(&self, spec: &[&str]) -> Result<usize, String> {
+        let code = self.vm.code();
+        match spec {
+            ["line", line] => {
+                let line: usize = line.parse().map_err(|_| format!("{} is not a line number", line))?;
+                code.locations.iter().position(|l| l.file == 0 && l.line == line)
+                    .ok_or(format!("no code on line {}", line))
+            }
+            [label] if label.starts_with(':') => {
+                let name = &label[1..];
+                code.labels.get(name).map(|t| usize::from(*t))
+                    .ok_or(format!("no label named {}", name))
+            }
+            [offset] => offset.parse().map_err(|_| format!("{} is not an offset", offset)),
+            _ => Err("expected an offset, `line <n>` or `:<label>`".to_string()),
+        }
+    }
+
+    /// executes `moves` movesets, stopping early at breakpoints and watchpoints
+    pub fn step(&mut self, moves: usize) -> Stop {
+        let target = self.vm.steps() + moves as u64;
+        self.resume(|vm| vm.steps() >= target)
+    }
+
+    /// executes `instructions` `;` submitted instructions
+    pub fn next(&mut self, instructions: usize) -> Stop {
+        let target = self.vm.instructions() + instructions as u64;
+        self.resume(|vm| vm.instructions() >= target)
+    }
+
+    /// runs until a breakpoint, watchpoint or the end
+    pub fn cont(&mut self) -> Stop {
+        self.resume(|_| false)
+    }
+
+    /// undoes `moves` movesets, stopping early at breakpoints and watchpoints
+    pub fn step_back(&mut self, moves: usize) -> Stop {
+        self.rewind(moves)
+    }
+
+    /// runs backwards to a breakpoint, watchpoint or as far as the journal goes
+    pub fn reverse_cont(&mut self) -> Stop {
+        self.rewind(usize::MAX)
+    }
+
+    fn rewind(&mut self, moves: usize) -> Stop {
+        let mut stop = Stop::Stepped;
+
+        for _ in 0..moves {
+            if !self.vm.step_back() {
+                stop = Stop::JournalStart;
+                break;
+            }
+            if let Some(watch) = changed(&self.watchpoints, &self.vm) {
+                stop = Stop::Watchpoint(watch);
+                break;
+            }
+            if self.vm.exec_pointer().is_none() && self.breakpoints.contains(&self.vm.program_counter()) {
+                stop = Stop::Breakpoint(self.vm.program_counter());
+                break;
+            }
+        }
+
+        for w in self.watchpoints.iter_mut() {
+            w.last = w.watch.read(&self.vm);
+        }
+        stop
+    }
+
+    fn resume(&mut self, mut done: impl FnMut(&VM) -> bool) -> Stop {
+        let Debugger { vm, breakpoints, watchpoints, .. } = self;
+
+        // the first step always goes, so we can leave the breakpoint we are sitting on
+        let mut first = true;
+        let mut stop = Stop::Stepped;
+
+        let result = vm.run_until(|vm| {
+            // before `done`, so the change made by the last step asked for still stops
+            if let Some(watch) = changed(watchpoints, vm) {
+                stop = Stop::Watchpoint(watch);
+                return true;
+            }
+            if done(vm) {
+                return true;
+            }
+            if !first && vm.exec_pointer().is_none() && breakpoints.contains(&vm.program_counter()) {
+                stop = Stop::Breakpoint(vm.program_counter());
+                return true;
+            }
+            first = false;
+            false
+        });
+
+        // a step that finished the program, hit a limit or failed is not checked by `run_until`
+        if let (Stop::Stepped, Some(watch)) = (stop, changed(watchpoints, vm)) {
+            stop = Stop::Watchpoint(watch);
+        }
+        for w in watchpoints.iter_mut() {
+            w.last = w.watch.read(vm);
+        }
+
+        match result {
+            Ok(Status::Running) => stop,
+            // the change made by the last step goes first, the next command gets the status
+            Ok(_) if stop != Stop::Stepped => stop,
+            Ok(status) => Stop::Vm(status),
+            Err(e) => {
+                match vm.exec_pointer() {
+                    Some(address) => println!("error in memory at {}: {}", address, e),
+                    None => println!("error at offset {}: {}", vm.program_counter() - 1, e),
+                }
+                stop
+            }
+        }
+    }
+
+    /// where the program counter is, in offsets, lines and labels
+    pub fn location(&self) -> String {
+        let pc = self.vm.program_counter();
+        let code = self.vm.code();
+
+        if let Some(address) = self.vm.exec_pointer() {
+            return format!("memory {}, run by EXEC before offset {}", address, pc);
+        }
+
+        let mut out = format!("offset {}", pc);
+        if let Some(moveset) = code.movesets.get(pc) {
+            out += &format!(" [{}]", codegen::moveset_name(*moveset).unwrap_or("?"));
+        }
+        if let Some((label, start)) = code.label_at(pc) {
+            out += &format!(" in :{}+{}", label, pc - start);
+        }
+        if let (Some(location), Some(position)) = (code.location(pc), code.position(pc)) {
+            // the source is the main file's
+            let text = self.source.get(location.line - 1).filter(|_| location.file == 0);
+            out += &format!(", {}: {}", position, text.map(|s| s.trim()).unwrap_or(""));
+        }
+        out
+    }
+
+    /// reads commands from stdin until `quit` or the end of input
+    pub fn repl(&mut self) {
+        println!("{}", self.location());
+
+        let stdin = io::stdin();
+        loop {
+            print!("(cubdbg) ");
+            let _ = io::stdout().flush();
+
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let count = |words: &[&str]| words.get(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+
+            let stop = match words.as_slice() {
+                [] => continue,
+                ["quit" | "q"] => return,
+                ["help" | "h"] => { println!("{}", HELP); continue }
+                ["step" | "s", ..] => self.step(count(&words)),
+                ["next" | "n", ..] => self.next(count(&words)),
+                ["continue" | "c"] => self.cont(),
+                ["back" | "bs", ..] => self.step_back(count(&words)),
+                ["rcontinue" | "rc"] => self.reverse_cont(),
+                ["break" | "b", spec @ ..] => {
+                    match self.resolve(spec) {
+                        Ok(offset) => {
+                            self.add_breakpoint(offset);
+                            println!("breakpoint at offset {}", offset);
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                ["delete" | "d", spec @ ..] => {
+                    match self.resolve(spec) {
+                        Ok(offset) if self.remove_breakpoint(offset) => println!("removed breakpoint at offset {}", offset),
+                        Ok(offset) => println!("no breakpoint at offset {}", offset),
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                ["watch" | "w", kind, n] => {
+                    let watch = match (*kind, n.parse()) {
+                        ("mem", Ok(n)) => Watch::Memory(n),
+                        ("stack", Ok(n)) => Watch::Stack(n),
+                        _ => { println!("expected `watch mem <address>` or `watch stack <slot>`"); continue }
+                    };
+                    self.add_watchpoint(watch);
+                    println!("watching {:?}", watch);
+                    continue;
+                }
+                ["print" | "p", what @ ..] => { self.print(what); continue }
+                ["info" | "i"] => { self.info(); continue }
+                ["save", path] => {
+                    match self.vm.snapshot().save(path) {
+                        Ok(()) => println!("saved the VM state to {}", path),
+                        Err(e) => println!("failed to write snapshot to {}: {}", path, e),
+                    }
+                    continue;
+                }
+                _ => { println!("unknown command, try `help`"); continue }
+            };
+
+            match stop {
+                Stop::Stepped => {}
+                Stop::Breakpoint(offset) => println!("breakpoint at offset {}", offset),
+                Stop::Watchpoint(watch) => println!("{:?} changed", watch),
+                Stop::Vm(Status::Finished) => println!("program finished"),
+                Stop::Vm(Status::Halted(exit_status)) => println!("program halted with exit status {}", exit_status),
+                Stop::Vm(Status::LimitReached(limit)) => println!("stopped: {}", limit),
+                Stop::Vm(Status::Running) => {}
+                Stop::JournalStart => println!("reached the start of the journal"),
+            }
+            println!("{}", self.location());
+        }
+    }
+
+    fn print(&self, what: &[&str]) {
+        let vm = &self.vm;
+        match what {
+            ["current"] => print_cube("current", vm.current()),
+            ["immediate"] => print_cube("immediate", vm.immediate()),
+            ["stack"] => {
+                if vm.stack().is_empty() {
+                    println!("stack is empty");
+                }
+                for (i, cube) in vm.stack().iter().enumerate().rev() {
+                    print_cube(&format!("stack[{}]", i + 1), cube);
+                }
+            }
+            ["mem", address] => match address.parse::<usize>().ok().and_then(|a| vm.memory().get(a)) {
+                Some(cube) => print_cube(&format!("mem[{}]", address), &cube),
+                None => println!("{} is not a memory address", address),
+            },
+            _ => println!("expected current, immediate, stack or mem <address>"),
+        }
+    }
+
+    fn info(&self) {
+        let vm = &self.vm;
+        println!("pc {}  sp {}  mp {}  return stack {:?}", vm.program_counter(), vm.stack_pointer(), vm.mem_pointer(), vm.return_stack());
+        if let Some(address) = vm.exec_pointer() {
+            println!("running the instructions in memory from {}", address);
+        }
+        println!("steps {}  instructions {}", vm.steps(), vm.instructions());
+        println!("breakpoints {:?}", self.breakpoints);
+        for w in &self.watchpoints {
+            println!("watching {:?}", w.watch);
+        }
+    }
+}
+
+fn print_cube(name: &str, cube: &Cube) {
+    println!("{} (U sum {})", name, cube.sum_face(cube::U));
+    print!("{}", cube);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::io::test_vm;
+
+    fn debugger(source: &str) -> Debugger {
+        Debugger::new(test_vm(source, b"").0, vec![])
+    }
+
+    #[test]
+    fn watchpoint_stops_on_the_last_step_asked_for() {
+        let mut debugger = debugger("R U , F D F' ; R ;");
+        debugger.add_watchpoint(Watch::Stack(1));
+        // the 7th step is the `;` that pushes
+        assert_eq!(debugger.step(7), Stop::Watchpoint(Watch::Stack(1)));
+        assert_eq!(debugger.step(1), Stop::Stepped);
+    }
+
+    #[test]
+    fn watchpoint_stops_on_the_step_that_finishes() {
+        let mut debugger = debugger("R U , F D F' ;");
+        debugger.add_watchpoint(Watch::Stack(1));
+        assert_eq!(debugger.cont(), Stop::Watchpoint(Watch::Stack(1)));
+        assert_eq!(debugger.cont(), Stop::Vm(Status::Finished));
+    }
+
+    #[test]
+    fn reverse_cont_stops_at_a_breakpoint() {
+        let mut debugger = debugger("R , F D F' ;\nmid: R U ;\nF' R , F D F' ; R' F' ;");
+        let mid = debugger.resolve(&[":mid"]).unwrap();
+        debugger.add_breakpoint(mid);
+        assert_eq!(debugger.cont(), Stop::Breakpoint(mid));
+        assert_eq!(debugger.cont(), Stop::Vm(Status::Halted(7)));
+
+        assert_eq!(debugger.reverse_cont(), Stop::Breakpoint(mid));
+        assert_eq!(debugger.vm().program_counter(), mid);
+        assert_eq!(debugger.vm().stack_pointer(), 1);
+        assert_eq!(debugger.reverse_cont(), Stop::JournalStart);
+        assert_eq!(debugger.vm().program_counter(), 1);
+    }
+}
@@ -0,0 +1,274 @@
+use std::array;
+
+use crate::lang::codegen::Code;
+use crate::vm::cube::Cube;
+use crate::vm::vm::apply_move;
+
+/// where every facelet of a cube moves to, as one table for any number of moves.
+///
+/// the faces are laid out one after the other, the new cube takes facelet `i` from `old[perm[i]]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permutation([u8; 54]);
+
+impl Permutation {
+    /// the permutation of a move moveset, worked out by making the move on a cube with numbered facelets
+    pub fn of_move(moveset: u16) -> Permutation {
+        let mut cube = Cube::default();
+        for (i, facelet) in cube.faces.iter_mut().flatten().enumerate() {
+            *facelet = i as u8;
+        }
+        apply_move(&mut cube, moveset);
+        Permutation(flatten(&cube))
+    }
+
+    /// this permutation followed by `next`
+    pub fn then(&self, next: &Permutation) -> Permutation {
+        Permutation(array::from_fn(|i| self.0[next.0[i] as usize]))
+    }
+
+    pub fn apply(&self, cube: &mut Cube) {
+        let old = flatten(cube);
+        for (i, facelet) in cube.faces.iter_mut().flatten().enumerate() {
+            *facelet = old[self.0[i] as usize];
+        }
+    }
+}
+
+fn flatten(cube: &Cube) -> [u8; 54] {
+    let mut flat = [0; 54];
+    for (i, facelet) in cube.faces.iter().flatten().enumerate() {
+        flat[i] = *facelet;
+    }
+    flat
+}
+
+/// what runs at an offset of the code, `next` is where the program counter goes after it.
+///
+/// every op stands for `count` movesets and takes as many steps as they would
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// the moves from here up to the next marker, fused
+    Moves { perm: Permutation, count: u64, next: usize },
+    /// moves that always start from a fresh cube and so always end in the same one, `next` is the `,` or `;` after them
+    Fresh { cube: Cube, count: u64, next: usize },
+    /// a whole `immediate , opcode ;` instruction that always submits the same cubes, `next` is past the `;`
+    Submit { opcode: Cube, immediate: Cube, count: u64, next: usize },
+    /// a marker or jump, executed one moveset at a time
+    Single,
+}
+
+/// what is known about the in-flight cubes before an offset runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Fresh {
+    /// anything goes
+    Neither,
+    /// the current cube is fresh, after a `,`
+    Current,
+    /// both cubes are fresh, at the start and after a `;`
+    Both,
+    /// not reached yet
+    Unreached,
+}
+
+/// decodes verified code into an op for every offset.
+///
+/// an op for an offset in the middle of fused moves covers the rest of them, so jumps
+/// and single steps can stop anywhere and pick the fused ops back up from there.
+pub fn decode(code: &Code) -> Vec<Op> {
+    let movesets = &code.movesets;
+    let mut ops = vec![Op::Single; movesets.len()];
+
+    // offsets that start a moveset, operands do not
+    let mut starts = vec![];
+    let mut offset = 1;
+    while offset < movesets.len() {
+        starts.push(offset);
+        offset += match movesets[offset] {
+            0x003A | 0x003D | 0x003E => 2,
+            _ => 1,
+        };
+    }
+
+    let moves: Vec<Permutation> = (0..=27).map(Permutation::of_move).collect();
+
+    // fuse back to front, every move followed by the op after it
+    for &offset in starts.iter().rev() {
+        let moveset = movesets[offset];
+        if moveset > 27 {
+            continue;
+        }
+        let perm = moves[usize::from(moveset)];
+
+        ops[offset] = match ops.get(offset + 1) {
+            Some(Op::Moves { perm: rest, count, next }) => {
+                Op::Moves { perm: perm.then(rest), count: count + 1, next: *next }
+            }
+            _ => Op::Moves { perm, count: 1, next: offset + 1 },
+        };
+    }
+
+    let fresh = freshness(movesets, &starts);
+
+    // the moves from `start` up to a `,` or `;`, made on a fresh cube
+    let segment = |start: usize| -> Option<(Cube, u64, usize)> {
+        let mut cube = Cube::new();
+        let mut marker = start;
+        while let Some(moveset @ 0..=27) = movesets.get(marker).copied() {
+            apply_move(&mut cube, moveset);
+            marker += 1;
+        }
+        match movesets.get(marker) {
+            Some(0x002C | 0x003B) => Some((cube, (marker - start) as u64, marker)),
+            _ => None,
+        }
+    };
+
+    for &offset in &starts {
+        let Some((cube, count, marker)) = segment(offset) else {
+            continue;
+        };
+
+        ops[offset] = match (fresh[offset], movesets[marker]) {
+            // `opcode ;`, the immediate is still fresh
+            (Fresh::Both, 0x003B) => Op::Submit { opcode: cube, immediate: Cube::new(), count: count + 1, next: marker + 1 },
+            // `immediate , opcode ;`
+            (Fresh::Both, _) => match segment(marker + 1) {
+                Some((opcode, rest, end)) if movesets[end] == 0x003B => {
+                    Op::Submit { opcode, immediate: cube, count: count + rest + 2, next: end + 1 }
+                }
+                _ if count > 0 => Op::Fresh { cube, count, next: marker },
+                _ => continue,
+            },
+            (Fresh::Current, _) if count > 0 => Op::Fresh { cube, count, next: marker },
+            _ => continue,
+        };
+    }
+
+    ops
+}
+
+/// works out which offsets always run with fresh cubes, following falls through and jumps until nothing changes.
+///
+/// return addresses are never fresh, the callee can leave anything in the cubes
+fn freshness(movesets: &[u16], starts: &[usize]) -> Vec<Fresh> {
+    let mut fresh = vec![Fresh::Unreached; movesets.len() + 1];
+    if let Some(first) = fresh.get_mut(1) {
+        *first = Fresh::Both;
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut meet = |fresh: &mut Vec<Fresh>, offset: usize, state: Fresh| {
+            if state < fresh[offset] {
+                fresh[offset] = state;
+                changed = true;
+            }
+        };
+
+        for &offset in starts {
+            let state = fresh[offset];
+            if state == Fresh::Unreached {
+                continue;
+            }
+
+            match movesets[offset] {
+                0x002C => meet(&mut fresh, offset + 1, Fresh::Current),
+                0x003B => meet(&mut fresh, offset + 1, Fresh::Both),
+                0x003A => {
+                    meet(&mut fresh, usize::from(movesets[offset + 1]), state);
+                    meet(&mut fresh, offset + 2, Fresh::Neither);
+                }
+                0x003D => {
+                    meet(&mut fresh, usize::from(movesets[offset + 1]), state);
+                    meet(&mut fresh, offset + 2, state);
+                }
+                0x003E => meet(&mut fresh, usize::from(movesets[offset + 1]), state),
+                0x003C => {}
+                _ => meet(&mut fresh, offset + 1, Fresh::Neither),
+            }
+        }
+    }
+
+    fresh
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::io::test_vm;
+    use crate::vm::limits::VmLimits;
+    use crate::vm::snapshot::Snapshot;
+    use crate::vm::error::VmError;
+    use crate::vm::vm::{Status, VM};
+
+    const PROGRAM: &str = "
+        U F' , F D F' ;             // PSH the PUTN opcode
+        * , R' ;                    // STORE it at 2 and 3
+        U' F X2 , F D F' ;          // PSH 101
+        R , U B' L' ;               // EXEC 3 cells back, at 2, which prints 101
+        :push3
+        U F' ;                      // PUTN
+
+        * , F D F' ;                // PSH 0, 1 and 3
+        F D F' , F D F' ;
+        R , F D F' ;
+        loop: R U R' U' =loop       // jumps back with the moves of the last time round still made
+        R , F D F' ; U F' ;         // PSH what the loop left, PUTN
+
+        F D F' , F D F' ;           // PSH 1 and 0
+        * , F D F' ;
+        R U2 mid: F' , F D F' ; U F' ;
+        =done
+        U >mid                      // into the middle of a line, with U already made
+        done:
+
+        F D F' , F D F' ;           // PSH 1 and 0
+        * , F D F' ;
+        top: R , F D F' ; U F' ;    // PSH, PUTN
+        =end
+        U2 >top                     // to the start of a line, with U2 already made
+        end:
+
+        F' R , F D F' ; R' F' ;     // HALT with 7
+
+        push3: R , F D F' ; <
+    ";
+
+    /// what a run stopped with and the whole state of the VM after it
+    type Stop = (Result<Status, VmError>, Snapshot, String);
+
+    /// runs to the first stop under the limits, then on to the end without them.
+    /// `fused` runs the decoded ops, otherwise a moveset at a time
+    fn run(limits: VmLimits, fused: bool) -> [Stop; 2] {
+        let (mut vm, io) = test_vm(PROGRAM, b"");
+
+        let stop = |vm: &mut VM| {
+            let result = if fused { vm.run() } else { vm.run_until(|_| false) };
+            (result, vm.snapshot(), io.output_string())
+        };
+        vm.set_limits(limits);
+        let first = stop(&mut vm);
+        vm.set_limits(VmLimits::default());
+        [first, stop(&mut vm)]
+    }
+
+    #[test]
+    fn program_runs_to_the_end() {
+        let [_, (result, snapshot, output)] = run(VmLimits::default(), true);
+        assert_eq!(result, Ok(Status::Halted(7)));
+        // 101 from memory, 3 from the call, 9 from the loop, then twice from each line jumped into
+        assert_eq!(output, "1013914836");
+        assert!(snapshot.steps > 100);
+    }
+
+    #[test]
+    fn fused_ops_stop_where_single_steps_do() {
+        for n in 0..=200 {
+            let step_limits = VmLimits { max_steps: Some(n), ..VmLimits::default() };
+            let instruction_limits = VmLimits { max_instructions: Some(n), ..VmLimits::default() };
+            for limits in [step_limits, instruction_limits] {
+                assert_eq!(run(limits, true), run(limits, false), "{:?}", limits);
+            }
+        }
+    }
+}
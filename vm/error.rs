@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// why the VM could not execute the next step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    /// the code does not start with the 0x00B0 marker
+    CorruptBytecode,
+    /// a jump at this offset is missing its target
+    MissingOperand(usize),
+    /// not a move or a marker
+    InvalidMoveset { offset: usize, moveset: u16 },
+    /// a jump to somewhere that is not the start of a moveset
+    InvalidJumpTarget { offset: usize, target: usize },
+    /// more movesets than jumps can address
+    CodeTooLarge(usize),
+    StackOverflow,
+    StackUnderflow,
+    ReturnStackUnderflow,
+    /// the memory pointer walked off the end of memory
+    MemoryOutOfBounds(usize),
+    /// EXEC pointed back further than the start of memory
+    ExecBeforeMemory { mem_pointer: usize, back: u8 },
+    /// SYSCALL with a number no host function is registered under
+    UnknownSyscall(u8),
+    /// a host function failed, with its number and why
+    Syscall(u8, String),
+    /// the snapshot does not fit this VM
+    InvalidSnapshot(&'static str),
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::CorruptBytecode => write!(f, "corrupt bytecode, missing the 0x00B0 start marker"),
+            VmError::MissingOperand(offset) => write!(f, "jump at offset {} has no target", offset),
+            VmError::InvalidMoveset { offset, moveset } => write!(f, "invalid moveset {:#06X} at offset {}", moveset, offset),
+            VmError::InvalidJumpTarget { offset, target } => write!(f, "jump at offset {} targets {}, which is not the start of a moveset", offset, target),
+            VmError::CodeTooLarge(len) => write!(f, "{} movesets do not fit in 16 bit jump targets", len),
+            VmError::StackOverflow => write!(f, "cannot push full stack"),
+            VmError::StackUnderflow => write!(f, "cannot pop empty stack"),
+            VmError::ReturnStackUnderflow => write!(f, "popped empty return stack"),
+            VmError::MemoryOutOfBounds(address) => write!(f, "memory address {} is out of bounds", address),
+            VmError::ExecBeforeMemory { mem_pointer, back } => write!(f, "EXEC {} cells back from memory address {} is before the start of memory", back, mem_pointer),
+            VmError::UnknownSyscall(number) => write!(f, "no syscall {}", number),
+            VmError::Syscall(number, reason) => write!(f, "syscall {} failed: {}", number, reason),
+            VmError::InvalidSnapshot(reason) => write!(f, "invalid snapshot: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
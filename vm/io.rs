@@ -0,0 +1,171 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read, Write};
+use std::rc::Rc;
+
+/// the character and number streams a program talks to.
+///
+/// the VM only ever moves single bytes through this,
+/// number formatting and parsing happens in the VM itself.
+pub trait Io {
+    /// writes the bytes to the output
+    fn write(&mut self, bytes: &[u8]);
+
+    /// reads a single byte from the input, `None` on end of input
+    fn read_byte(&mut self) -> Option<u8>;
+
+    /// the byte `read_byte` would return next, without taking it from the input
+    fn peek_byte(&mut self) -> Option<u8>;
+}
+
+/// talks to the terminal through stdin and stdout
+#[derive(Default)]
+pub struct StdIo;
+
+impl Io for StdIo {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut out = io::stdout().lock();
+        // a closed stdout is not the program's problem
+        let _ = out.write_all(bytes);
+        let _ = out.flush();
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0u8; 1];
+        match io::stdin().lock().read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    fn peek_byte(&mut self) -> Option<u8> {
+        // stdin is buffered, the byte stays there for the next read
+        io::stdin().lock().fill_buf().ok()?.first().copied()
+    }
+}
+
+/// in-memory input and output, for feeding programs and checking what they print.
+///
+/// clones share the same buffers, so keep one around after handing the other to the VM.
+#[derive(Clone, Default)]
+pub struct BufferIo {
+    input: Rc<RefCell<VecDeque<u8>>>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl BufferIo {
+    pub fn new(input: &[u8]) -> BufferIo {
+        BufferIo {
+            input: Rc::new(RefCell::new(input.iter().copied().collect())),
+            output: Rc::new(RefCell::new(vec![])),
+        }
+    }
+
+    /// everything the program has written so far
+    pub fn output(&self) -> Vec<u8> {
+        self.output.borrow().clone()
+    }
+
+    /// the output so far, lossily decoded as UTF-8
+    pub fn output_string(&self) -> String {
+        String::from_utf8_lossy(&self.output.borrow()).into_owned()
+    }
+
+    /// adds more bytes to the end of the input
+    pub fn feed(&self, bytes: &[u8]) {
+        self.input.borrow_mut().extend(bytes);
+    }
+}
+
+impl Io for BufferIo {
+    fn write(&mut self, bytes: &[u8]) {
+        self.output.borrow_mut().extend_from_slice(bytes);
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        self.input.borrow_mut().pop_front()
+    }
+
+    fn peek_byte(&mut self) -> Option<u8> {
+        self.input.borrow().front().copied()
+    }
+}
+
+/// a VM with the program compiled from `source` loaded, reading `input` and writing to the returned buffer.
+///
+/// the opcodes test programs use, and the moves that make them:
+///
+/// | opcode | moves    | opcode | moves   | opcode  | moves     |
+/// | ---    | ---      | ---    | ---     | ---     | ---       |
+/// | PSH    | `F D F'` | PUTC   | `F' R`  | SYSCALL | `U L F`   |
+/// | POP    | `F U L'` | PUTN   | `U F'`  | STORE   | `R'`      |
+/// | MEM    | `R`      | GETC   | `X`     | EXEC    | `U B' L'` |
+/// | RET    | `F'`     | GETN   | `U' R`  | HALT    | `R' F'`   |
+///
+/// and as arguments, `F U L'` is 2, `R` 3, `F'` 6, `Z` 72, `U' F X2` 101, `U2 R X2` 105 and `X2` 144
+#[cfg(test)]
+pub(crate) fn test_vm(source: &str, input: &[u8]) -> (crate::vm::vm::VM, BufferIo) {
+    let code = crate::lang::compiler::compile_source("test.cubasm", source, &mut vec![]).unwrap();
+    let io = BufferIo::new(input);
+    let mut vm = crate::vm::vm::VM::new();
+    vm.set_io(Box::new(io.clone()));
+    vm.load(code).unwrap();
+    (vm, io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// runs the program on the input and returns what it printed
+    fn run(source: &str, input: &[u8]) -> String {
+        let (mut vm, io) = test_vm(source, input);
+        vm.run().unwrap();
+        io.output_string()
+    }
+
+    #[test]
+    fn putc_writes_a_character() {
+        // 72 and 105
+        assert_eq!(run("Z , F D F' ; F' R ;\nU2 R X2 , F D F' ; F' R ;", b""), "Hi");
+    }
+
+    #[test]
+    fn putn_writes_a_decimal_number() {
+        // 101, then the 0 of a solved cube
+        assert_eq!(run("U' F X2 , F D F' ; U F' ;\n* , F D F' ; U F' ;", b""), "1010");
+    }
+
+    #[test]
+    fn getc_reads_characters_then_0_at_the_end() {
+        assert_eq!(run("X ; X ; U F' ; F' R ;", b"a"), "0a");
+    }
+
+    #[test]
+    fn getn_skips_whitespace() {
+        assert_eq!(run("U' R ; U F' ;", b" \t\n 42 "), "42");
+    }
+
+    #[test]
+    fn getn_wraps_around_at_256() {
+        assert_eq!(run("U' R ; U F' ;", b"300"), "44");
+    }
+
+    #[test]
+    fn getn_without_digits_reads_0() {
+        assert_eq!(run("U' R ; U F' ;", b"x"), "0");
+    }
+
+    #[test]
+    fn getn_leaves_the_byte_after_the_digits() {
+        assert_eq!(run("U' R ; X ; F' R ; U F' ;", b"12a"), "a12");
+    }
+
+    #[test]
+    fn feed_adds_input_later() {
+        let io = BufferIo::new(b"1");
+        io.feed(b"2");
+        let mut reader = io.clone();
+        assert_eq!([reader.read_byte(), reader.peek_byte(), reader.read_byte(), reader.read_byte()], [Some(b'1'), Some(b'2'), Some(b'2'), None]);
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+
+use crate::vm::cube::Cube;
+
+/// a change made by a step that cannot be worked out backwards from the code.
+///
+/// moves are not in here, every move is undone by its inverse move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    /// the in-flight cubes before a `,` or `;` replaced them
+    InFlight { current: Cube, immediate: Cube },
+    StackSlot { slot: usize, old: Cube },
+    StackPointer(usize),
+    Memory { address: usize, old: Cube },
+    MemPointer(usize),
+    /// a return address was pushed
+    ReturnPush,
+    /// this return address was popped
+    ReturnPop(usize),
+}
+
+/// everything needed to take a single step back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub program_counter: usize,
+    pub exec_pointer: Option<usize>,
+    pub steps: u64,
+    pub instructions: u64,
+    /// in the order they happened
+    pub effects: Vec<Effect>,
+}
+
+/// the undo history of a VM, forgetting the oldest steps past its capacity
+#[derive(Debug, Clone)]
+pub struct Journal {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Journal {
+        Journal {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// the most recent step
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
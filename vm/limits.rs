@@ -0,0 +1,55 @@
+use std::fmt;
+use std::time::Instant;
+
+/// how far a program is allowed to go before the VM stops it.
+///
+/// hitting a limit is not an error, the VM stops before the step that would
+/// cross it and can be resumed after raising the limit with `VM::set_limits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmLimits {
+    /// total movesets (moves, `,`, `;` and jumps) to execute
+    pub max_steps: Option<u64>,
+    /// total `;` submitted instructions to execute
+    pub max_instructions: Option<u64>,
+    /// deepest the stack can get, `None` for as deep as it is big,
+    /// where pushing onto a full stack is `VmError::StackOverflow` rather than a limit
+    pub max_stack_depth: Option<usize>,
+    /// deepest calls can nest
+    pub max_return_depth: usize,
+    /// wall-clock time to stop at
+    pub deadline: Option<Instant>,
+}
+
+impl Default for VmLimits {
+    fn default() -> Self {
+        VmLimits {
+            max_steps: None,
+            max_instructions: None,
+            max_stack_depth: None,
+            max_return_depth: 1024,
+            deadline: None,
+        }
+    }
+}
+
+/// the limit that stopped the VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Instructions,
+    StackDepth,
+    ReturnDepth,
+    Deadline,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Steps => write!(f, "step limit reached"),
+            Limit::Instructions => write!(f, "instruction limit reached"),
+            Limit::StackDepth => write!(f, "stack depth limit reached"),
+            Limit::ReturnDepth => write!(f, "return stack depth limit reached"),
+            Limit::Deadline => write!(f, "deadline passed"),
+        }
+    }
+}
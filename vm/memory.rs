@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use crate::vm::cube::Cube;
+
+/// cells per page
+pub const PAGE_SIZE: usize = 256;
+
+/// the memory of a VM, one cube per address.
+///
+/// pages are only allocated when something is written to them, reading a cell
+/// that was never written gives a blank cube. a large memory costs nothing until it is used.
+#[derive(Debug, Clone, Default)]
+pub struct Memory {
+    pages: HashMap<usize, Box<[Cube; PAGE_SIZE]>>,
+    size: usize,
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Memory {
+        Memory {
+            pages: HashMap::new(),
+            size,
+        }
+    }
+
+    /// how many addresses there are
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// the cell at `address`, `None` past the end
+    pub fn get(&self, address: usize) -> Option<Cube> {
+        if address >= self.size {
+            return None;
+        }
+        Some(match self.pages.get(&(address / PAGE_SIZE)) {
+            Some(page) => page[address % PAGE_SIZE],
+            None => Cube::default(),
+        })
+    }
+
+    /// overwrites the cell at `address`, panics past the end
+    pub fn set(&mut self, address: usize, cube: Cube) {
+        // blank cells of missing pages are already blank
+        if cube == Cube::default() && !self.pages.contains_key(&(address / PAGE_SIZE)) {
+            assert!(address < self.size, "memory address {} is out of bounds", address);
+            return;
+        }
+        *self.cell_mut(address) = cube;
+    }
+
+    /// the cell at `address`, allocating its page. panics past the end
+    pub fn cell_mut(&mut self, address: usize) -> &mut Cube {
+        assert!(address < self.size, "memory address {} is out of bounds", address);
+        let page = self.pages.entry(address / PAGE_SIZE).or_insert_with(|| Box::new([Cube::default(); PAGE_SIZE]));
+        &mut page[address % PAGE_SIZE]
+    }
+
+    /// every cell that is not blank, by address
+    pub fn cells(&self) -> Vec<(usize, Cube)> {
+        let mut pages: Vec<_> = self.pages.iter().collect();
+        pages.sort_by_key(|(number, _)| **number);
+
+        pages.into_iter()
+            .flat_map(|(number, page)| page.iter().enumerate().map(move |(i, cube)| (number * PAGE_SIZE + i, *cube)))
+            .filter(|(_, cube)| *cube != Cube::default())
+            .collect()
+    }
+
+    /// blanks every cell and frees all pages
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
@@ -1,2 +1,16 @@
+#[allow(clippy::module_inception)]
 pub mod vm;
-mod cube;
\ No newline at end of file
+pub mod config;
+pub mod cube;
+pub mod debugger;
+pub mod decode;
+pub mod error;
+pub mod io;
+pub mod journal;
+pub mod limits;
+pub mod memory;
+pub mod profiler;
+pub mod snapshot;
+pub mod syscall;
+pub mod trace;
+pub mod verify;
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::lang::codegen::{self, Code};
+use crate::vm::vm::VM;
+
+/// a label, by the offset it was defined at. `None` is the code before the first label
+type Frame = Option<usize>;
+
+/// execution counts per label
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LabelStats {
+    /// times it was entered with a CALL
+    pub calls: u64,
+    /// movesets executed in the label itself
+    pub steps: u64,
+    /// twists and rotations executed in the label itself
+    pub moves: u64,
+    /// twists and rotations executed in the label and everything it called
+    pub inclusive_moves: u64,
+}
+
+/// the state of the VM right before a step, see `Profile::record`
+pub struct Sample {
+    pc: usize,
+    moveset: u16,
+    /// run by EXEC, counted against the label it was run from
+    from_memory: bool,
+    steps: u64,
+    stack: Vec<Frame>,
+}
+
+/// where a program spends its steps and twists.
+///
+/// calls are attributed through the return stack: every return address points
+/// just past a CALL, and the label around that CALL is the caller.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    code: Code,
+    /// executions per bytecode offset
+    hits: Vec<u64>,
+    labels: HashMap<Frame, LabelStats>,
+    /// moves per call stack, outermost frame first
+    stacks: HashMap<Vec<Frame>, u64>,
+}
+
+impl Profile {
+    pub fn new(code: &Code) -> Profile {
+        Profile {
+            code: code.clone(),
+            hits: vec![0; code.movesets.len()],
+            labels: HashMap::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    /// takes a sample of the VM, call this right before `VM::step`
+    pub fn sample(&self, vm: &VM) -> Sample {
+        let mut stack: Vec<Frame> = vm.return_stack().iter()
+            .map(|ret| self.frame(ret.saturating_sub(2))) // the CALL and its operand
+            .collect();
+        stack.push(self.frame(vm.program_counter()));
+
+        Sample {
+            pc: vm.program_counter(),
+            moveset: match vm.exec_pointer() {
+                Some(_) => 0x003B, // an instruction from memory
+                None => vm.code().movesets.get(vm.program_counter()).copied().unwrap_or(0),
+            },
+            from_memory: vm.exec_pointer().is_some(),
+            steps: vm.steps(),
+            stack,
+        }
+    }
+
+    /// counts the step that was taken since `sample`, if any
+    pub fn record(&mut self, vm: &VM, sample: Sample) {
+        if vm.steps() == sample.steps {
+            return; // stopped on a limit
+        }
+
+        if let Some(hits) = self.hits.get_mut(sample.pc).filter(|_| !sample.from_memory) {
+            *hits += 1;
+        }
+
+        let is_move = sample.moveset <= 27;
+        let top = *sample.stack.last().unwrap();
+
+        let stats = self.labels.entry(top).or_default();
+        stats.steps += 1;
+        if is_move {
+            stats.moves += 1;
+        }
+        if sample.moveset == 0x003A {
+            let callee = self.frame(vm.program_counter());
+            self.labels.entry(callee).or_default().calls += 1;
+        }
+
+        if is_move {
+            for (i, frame) in sample.stack.iter().enumerate() {
+                // recursion puts a label on the stack more than once
+                if !sample.stack[..i].contains(frame) {
+                    self.labels.entry(*frame).or_default().inclusive_moves += 1;
+                }
+            }
+            *self.stacks.entry(sample.stack).or_default() += 1;
+        }
+    }
+
+    fn frame(&self, offset: usize) -> Frame {
+        self.code.label_at(offset).map(|(_, start)| start)
+    }
+
+    fn name(&self, frame: Frame) -> String {
+        // the same pick as everywhere else when labels share an offset
+        match frame.and_then(|start| self.code.label_at(start)) {
+            Some((name, _)) => format!(":{}", name),
+            None => "(top)".to_string(),
+        }
+    }
+
+    /// counts for every label that ran, by the name it was defined with
+    pub fn labels(&self) -> Vec<(String, LabelStats)> {
+        let mut labels: Vec<_> = self.labels.iter().map(|(frame, stats)| (self.name(*frame), *stats)).collect();
+        labels.sort_by(|a, b| b.1.inclusive_moves.cmp(&a.1.inclusive_moves).then(a.0.cmp(&b.0)));
+        labels
+    }
+
+    /// how often every offset was executed
+    pub fn hits(&self) -> &[u64] {
+        &self.hits
+    }
+
+    /// a report of the labels, heaviest first, then every executed offset with its source line
+    pub fn write_report(&self, w: &mut impl Write, source: &[String]) -> io::Result<()> {
+        writeln!(w, "{:<20} {:>10} {:>12} {:>12} {:>12}", "label", "calls", "steps", "moves", "incl. moves")?;
+        for (name, stats) in self.labels() {
+            writeln!(w, "{:<20} {:>10} {:>12} {:>12} {:>12}", name, stats.calls, stats.steps, stats.moves, stats.inclusive_moves)?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "{:>8} {:>6} {:>4} {:>4} {:>12}  source", "offset", "line", "col", "op", "hits")?;
+        for (offset, hits) in self.hits.iter().enumerate().filter(|(_, hits)| **hits > 0) {
+            let location = self.code.locations.get(offset).copied().unwrap_or_default();
+            // the source is the main file's
+            let text = location.line.checked_sub(1).filter(|_| location.file == 0).and_then(|l| source.get(l)).map(|s| s.trim()).unwrap_or("");
+            let op = codegen::moveset_name(self.code.movesets[offset]).unwrap_or("?");
+            writeln!(w, "{:>8} {:>6} {:>4} {:>4} {:>12}  {}", offset, location.line, location.column, op, hits, text)?;
+        }
+        Ok(())
+    }
+
+    /// the moves per call stack in the folded format flamegraph tools read
+    pub fn write_folded(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .map(|(stack, moves)| {
+                let names: Vec<String> = stack.iter().map(|f| self.name(*f)).collect();
+                format!("{} {}", names.join(";"), moves)
+            })
+            .collect();
+        lines.sort();
+        for line in lines {
+            writeln!(w, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::io::test_vm;
+    use crate::vm::trace::run_traced;
+
+    #[test]
+    fn labels_at_the_same_offset_always_get_the_same_name() {
+        // a fresh label map, with its own hash order, every time round
+        for _ in 0..20 {
+            let (mut vm, _) = test_vm("R :b ;\n>end\nc: b: a: U ; <\nend:", b"");
+            let mut profile = Profile::new(vm.code());
+            run_traced(&mut vm, None::<&mut io::Sink>, Some(&mut profile)).unwrap().unwrap();
+
+            let names: Vec<String> = profile.labels().into_iter().map(|(name, _)| name).collect();
+            assert_eq!(names, ["(top)", ":a"]);
+            let mut folded = vec![];
+            profile.write_folded(&mut folded).unwrap();
+            assert!(String::from_utf8(folded).unwrap().contains("(top);:a "));
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::lang::codegen::Code;
+use crate::vm::cube::Cube;
+
+/// everything needed to pick a VM back up where it stopped.
+///
+/// on disk this is, all little endian:
+///
+/// | field            | layout                                    |
+/// | ---              | ---                                       |
+/// | magic            | `CUBS`                                    |
+/// | version          | u16                                       |
+/// | program counter  | u32                                       |
+/// | memory pointer   | u32                                       |
+/// | exec pointer     | u32, `0xFFFFFFFF` when not running EXEC   |
+/// | halted           | u16 exit status, `0xFFFF` when not halted |
+/// | steps            | u64                                       |
+/// | instructions     | u64                                       |
+/// | current          | cube                                      |
+/// | immediate        | cube                                      |
+/// | stack            | u32 count, cubes from slot 0 up           |
+/// | memory           | u32 count, u32 address and cube per cell  |
+/// | return stack     | u32 count, u32 addresses                  |
+/// | code             | u32 count, u16 movesets                   |
+///
+/// a cube is its 54 stickers, face by face in U F R B L D order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub program_counter: usize,
+    pub mem_pointer: usize,
+    pub exec_pointer: Option<usize>,
+    /// the exit status of the HALT that stopped the VM
+    pub halted: Option<u8>,
+    pub steps: u64,
+    pub instructions: u64,
+    pub current: Cube,
+    pub immediate: Cube,
+    /// the stack from the bottom up, including the empty slot 0 under it
+    pub stack: Vec<Cube>,
+    /// only the cells that were written to, by address
+    pub memory: Vec<(usize, Cube)>,
+    pub return_stack: Vec<usize>,
+    pub code: Code,
+}
+
+const MAGIC: &[u8; 4] = b"CUBS";
+pub const VERSION: u16 = 1;
+/// marks an exec pointer of `None`
+const NO_EXEC: u32 = u32::MAX;
+/// marks a VM that has not halted
+const NOT_HALTED: u16 = u16::MAX;
+
+impl Snapshot {
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+
+        write_u32(w, self.program_counter)?;
+        write_u32(w, self.mem_pointer)?;
+        match self.exec_pointer {
+            Some(address) if address < NO_EXEC as usize => write_u32(w, address)?,
+            Some(_) => return Err(invalid("value does not fit in 32 bits")),
+            None => w.write_all(&NO_EXEC.to_le_bytes())?,
+        }
+        w.write_all(&self.halted.map_or(NOT_HALTED, u16::from).to_le_bytes())?;
+        w.write_all(&self.steps.to_le_bytes())?;
+        w.write_all(&self.instructions.to_le_bytes())?;
+        write_cube(w, &self.current)?;
+        write_cube(w, &self.immediate)?;
+
+        write_u32(w, self.stack.len())?;
+        for cube in &self.stack {
+            write_cube(w, cube)?;
+        }
+
+        write_u32(w, self.memory.len())?;
+        for (address, cube) in &self.memory {
+            write_u32(w, *address)?;
+            write_cube(w, cube)?;
+        }
+
+        write_u32(w, self.return_stack.len())?;
+        for address in &self.return_stack {
+            write_u32(w, *address)?;
+        }
+
+        write_u32(w, self.code.movesets.len())?;
+        for moveset in &self.code.movesets {
+            w.write_all(&moveset.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read(r: &mut impl Read) -> io::Result<Snapshot> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a cubvm snapshot"));
+        }
+
+        let mut version = [0u8; 2];
+        r.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version != VERSION {
+            return Err(invalid("unsupported snapshot version"));
+        }
+
+        let program_counter = read_u32(r)?;
+        let mem_pointer = read_u32(r)?;
+        let exec_pointer = Some(read_u32(r)?).filter(|address| *address != NO_EXEC as usize);
+        let mut halted = [0u8; 2];
+        r.read_exact(&mut halted)?;
+        let halted = match u16::from_le_bytes(halted) {
+            NOT_HALTED => None,
+            exit_status => Some(u8::try_from(exit_status).map_err(|_| invalid("exit status does not fit in 8 bits"))?),
+        };
+        let steps = read_u64(r)?;
+        let instructions = read_u64(r)?;
+        let current = read_cube(r)?;
+        let immediate = read_cube(r)?;
+
+        let stack = (0..read_u32(r)?).map(|_| read_cube(r)).collect::<io::Result<_>>()?;
+        let memory = (0..read_u32(r)?)
+            .map(|_| Ok((read_u32(r)?, read_cube(r)?)))
+            .collect::<io::Result<_>>()?;
+        let return_stack = (0..read_u32(r)?).map(|_| read_u32(r)).collect::<io::Result<_>>()?;
+        let movesets = (0..read_u32(r)?)
+            .map(|_| {
+                let mut b = [0u8; 2];
+                r.read_exact(&mut b)?;
+                Ok(u16::from_le_bytes(b))
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Snapshot {
+            program_counter,
+            mem_pointer,
+            exec_pointer,
+            halted,
+            steps,
+            instructions,
+            current,
+            immediate,
+            stack,
+            memory,
+            return_stack,
+            code: Code { movesets, ..Code::default() },
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Snapshot> {
+        Snapshot::read(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_u32(w: &mut impl Write, value: usize) -> io::Result<()> {
+    let value: u32 = value.try_into().map_err(|_| invalid("value does not fit in 32 bits"))?;
+    w.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<usize> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b) as usize)
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+fn write_cube(w: &mut impl Write, cube: &Cube) -> io::Result<()> {
+    for face in &cube.faces {
+        w.write_all(face)?;
+    }
+    Ok(())
+}
+
+fn read_cube(r: &mut impl Read) -> io::Result<Cube> {
+    let mut cube = Cube::default();
+    for face in cube.faces.iter_mut() {
+        r.read_exact(face)?;
+    }
+    Ok(cube)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::bytecode::format_tests::{rejects_wrong_header, round_trip};
+    use crate::vm::io::test_vm;
+    use crate::vm::vm::Status;
+
+    const PROGRAM: &str = "
+        U F' , F D F' ;     // PSH the PUTN opcode twice
+        U F' , F D F' ;
+        * , R' ;            // STORE them at 3 and 5
+        * , R' ;
+        U' F X2 , F D F' ;  // PSH 101 and 3
+        R , F D F' ;
+        F' , U B' L' ;      // EXEC 6 cells back, at 3
+    ";
+
+    fn read_back(snapshot: &Snapshot) -> Snapshot {
+        round_trip(|w| snapshot.write(w), Snapshot::read)
+    }
+
+    #[test]
+    fn round_trip_in_the_middle_of_exec() {
+        let (mut vm, io) = test_vm(PROGRAM, b"");
+        vm.run_until(|vm| vm.exec_pointer() == Some(5)).unwrap();
+        assert_eq!(io.output_string(), "3");
+
+        let snapshot = vm.snapshot();
+        let read = read_back(&snapshot);
+        // the code is saved without its labels and source map
+        let code = Code { movesets: snapshot.code.movesets.clone(), ..Code::default() };
+        assert_eq!(read, Snapshot { code, ..snapshot });
+
+        let (mut resumed, resumed_io) = test_vm("", b"");
+        resumed.restore(read).unwrap();
+        assert_eq!(resumed.run(), Ok(Status::Finished));
+        assert_eq!(vm.run(), Ok(Status::Finished));
+        assert_eq!(resumed_io.output_string(), "101");
+        assert_eq!(resumed.snapshot(), Snapshot { code: resumed.code().clone(), ..vm.snapshot() });
+    }
+
+    #[test]
+    fn round_trip_before_running() {
+        let (mut vm, _) = test_vm("", b"");
+        vm.load(Code { movesets: vec![0xB0, 6, 0x3B], ..Code::default() }).unwrap();
+        assert_eq!(read_back(&vm.snapshot()), vm.snapshot());
+    }
+
+    #[test]
+    fn round_trip_after_halt() {
+        // HALT with 7
+        let (mut vm, _) = test_vm("F' R , F D F' ; R' F' ; U F' ;", b"");
+        assert_eq!(vm.run(), Ok(Status::Halted(7)));
+        let read = read_back(&vm.snapshot());
+        assert_eq!(read.halted, Some(7));
+
+        let (mut resumed, resumed_io) = test_vm("", b"");
+        resumed.restore(read).unwrap();
+        assert_eq!(resumed.run(), Ok(Status::Halted(7)));
+        assert_eq!(resumed_io.output_string(), "");
+    }
+
+    #[test]
+    fn rejects_wrong_magic_and_version() {
+        let header = [MAGIC.as_slice(), &VERSION.to_le_bytes()].concat();
+        rejects_wrong_header(Snapshot::read, &header, "not a cubvm snapshot", "unsupported snapshot version");
+    }
+
+    #[test]
+    fn rejects_truncated_snapshots() {
+        let (mut vm, _) = test_vm("", b"");
+        vm.load(Code { movesets: vec![0xB0, 6, 0x3B], ..Code::default() }).unwrap();
+        let mut bytes = vec![];
+        vm.snapshot().write(&mut bytes).unwrap();
+        bytes.pop();
+        assert!(Snapshot::read(&mut bytes.as_slice()).is_err());
+    }
+}
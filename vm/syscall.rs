@@ -0,0 +1,65 @@
+use crate::vm::cube::{self, Cube};
+use crate::vm::error::VmError;
+use crate::vm::memory::Memory;
+use crate::vm::vm::VM;
+
+/// a host function, called by SYSCALL with the number it was registered under
+pub type HostFn = Box<dyn FnMut(&mut Syscall<'_>) -> Result<(), VmError>>;
+
+/// what a host function gets to work with: the stack and memory of the VM that called it.
+///
+/// changes go through the VM, so they are checked like any instruction and can be
+/// undone with `VM::step_back`. anything done outside the VM, like writing a file, stays done.
+pub struct Syscall<'a> {
+    pub(crate) vm: &'a mut VM,
+}
+
+impl Syscall<'_> {
+    pub fn push(&mut self, cube: Cube) -> Result<(), VmError> {
+        self.vm.push(cube)
+    }
+
+    /// pushes a cube whose U face sums to `value`
+    pub fn push_value(&mut self, value: u8) -> Result<(), VmError> {
+        self.vm.push(Cube::with_value(value))
+    }
+
+    pub fn pop(&mut self) -> Result<Cube, VmError> {
+        self.vm.pop()
+    }
+
+    /// pops the top of the stack and returns the sum of its U face
+    pub fn pop_value(&mut self) -> Result<u8, VmError> {
+        Ok(self.vm.pop()?.sum_face(cube::U))
+    }
+
+    /// the stack from the bottom up, without the empty slot under it
+    pub fn stack(&self) -> &[Cube] {
+        self.vm.stack()
+    }
+
+    /// overwrites a cube on the stack, counted from the bottom starting at 1
+    pub fn set_stack(&mut self, slot: usize, cube: Cube) -> Result<(), VmError> {
+        if slot == 0 || slot > self.vm.stack_pointer() {
+            return Err(VmError::StackUnderflow);
+        }
+        self.vm.set_stack_slot(slot, cube);
+        Ok(())
+    }
+
+    pub fn memory(&self) -> &Memory {
+        self.vm.memory()
+    }
+
+    pub fn mem_pointer(&self) -> usize {
+        self.vm.mem_pointer()
+    }
+
+    pub fn read(&self, address: usize) -> Result<Cube, VmError> {
+        self.vm.memory().get(address).ok_or(VmError::MemoryOutOfBounds(address))
+    }
+
+    pub fn write(&mut self, address: usize, cube: Cube) -> Result<(), VmError> {
+        self.vm.write_memory(address, cube)
+    }
+}
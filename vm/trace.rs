@@ -0,0 +1,124 @@
+use std::io::{self, Write};
+
+use crate::lang::codegen;
+use crate::vm::cube;
+use crate::vm::error::VmError;
+use crate::vm::profiler::Profile;
+use crate::vm::vm::{Status, VM};
+
+/// one executed moveset.
+///
+/// `opcode` and `immediate` are the U face sums of the in-flight cubes when the
+/// moveset was read, so for a `;` they are exactly what got submitted.
+/// the pointers and the return stack depth are read after it ran.
+/// an instruction EXEC ran from memory has the `;` code, and `exec` is its address.
+/// `source` is the `file:line:column` the moveset was compiled from, when the code has a source map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub step: u64,
+    pub pc: usize,
+    pub exec: Option<usize>,
+    pub code: u16,
+    pub source: Option<String>,
+    pub opcode: u8,
+    pub immediate: u8,
+    pub stack_pointer: usize,
+    pub mem_pointer: usize,
+    pub return_depth: usize,
+}
+
+impl TraceEvent {
+    /// the event as a single line of JSON, without the line ending
+    pub fn to_json(&self) -> String {
+        let op = codegen::moveset_name(self.code).unwrap_or("?");
+        let exec = self.exec.map_or("null".to_string(), |address| address.to_string());
+        let source = self.source.as_deref().map_or("null".to_string(), json_string);
+        format!(
+            "{{\"step\":{},\"pc\":{},\"exec\":{},\"code\":{},\"op\":\"{}\",\"src\":{},\"opcode\":{},\"immediate\":{},\"sp\":{},\"mp\":{},\"rsp\":{}}}",
+            self.step, self.pc, exec, self.code, op, source, self.opcode, self.immediate,
+            self.stack_pointer, self.mem_pointer, self.return_depth,
+        )
+    }
+}
+
+/// a JSON string literal
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            c if c.is_control() => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// executes a single step and describes it, `None` when there was nothing to execute
+pub fn step_traced(vm: &mut VM) -> (Result<Status, VmError>, Option<TraceEvent>) {
+    let pc = vm.program_counter();
+    let exec = vm.exec_pointer();
+    let steps = vm.steps();
+    // EXEC runs code from memory, which has no source
+    let source = exec.is_none().then(|| vm.code().position(pc)).flatten();
+    let (code, opcode, immediate) = match exec {
+        Some(address) => {
+            let stored = |address| vm.memory().get(address).unwrap_or_default().sum_face(cube::U);
+            (Some(0x003B), stored(address), stored(address + 1))
+        }
+        None => (
+            vm.code().movesets.get(pc).copied(),
+            vm.current().sum_face(cube::U),
+            vm.immediate().sum_face(cube::U),
+        ),
+    };
+
+    let result = vm.step();
+
+    let event = match code {
+        // limits stop the VM before it executes anything
+        Some(code) if vm.steps() > steps => Some(TraceEvent {
+            step: vm.steps(),
+            pc,
+            exec,
+            code,
+            source,
+            opcode,
+            immediate,
+            stack_pointer: vm.stack_pointer(),
+            mem_pointer: vm.mem_pointer(),
+            return_depth: vm.return_stack().len(),
+        }),
+        _ => None,
+    };
+
+    (result, event)
+}
+
+/// runs the VM like `VM::run`, writing every executed moveset to `out` as a JSON line
+/// and sampling every step into `profile`, each only when it is given
+pub fn run_traced(vm: &mut VM, mut out: Option<&mut impl Write>, mut profile: Option<&mut Profile>) -> io::Result<Result<Status, VmError>> {
+    loop {
+        let sample = profile.as_ref().map(|p| p.sample(vm));
+        let (result, event) = step_traced(vm);
+
+        if let (Some(profile), Some(sample)) = (profile.as_mut(), sample) {
+            profile.record(vm, sample);
+        }
+        if let (Some(out), Some(event)) = (out.as_mut(), event) {
+            writeln!(out, "{}", event.to_json())?;
+        }
+
+        match result {
+            Ok(Status::Running) => {}
+            other => {
+                if let Some(out) = out.as_mut() {
+                    out.flush()?;
+                }
+                return Ok(other);
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::lang::codegen::Code;
+use crate::vm::error::VmError;
+
+/// checks that the code is safe to run, before any of it runs:
+///
+/// - it starts with the 0x00B0 marker
+/// - every moveset is a move, `*` or one of the `,` `;` `:` `<` `=` `>` markers
+/// - every jump has its operand, and the operand targets the start of a moveset or the end of the code
+/// - every offset can be a jump target, so the code fits in u16 addressing
+pub fn verify(code: &Code) -> Result<(), VmError> {
+    let movesets = &code.movesets;
+
+    if movesets.first() != Some(&0x00B0) {
+        return Err(VmError::CorruptBytecode);
+    }
+    if movesets.len() > usize::from(u16::MAX) {
+        return Err(VmError::CodeTooLarge(movesets.len()));
+    }
+
+    // where movesets start, operands are not executed
+    let mut starts = vec![false; movesets.len() + 1];
+    let mut jumps = vec![];
+
+    let mut offset = 1;
+    while offset < movesets.len() {
+        starts[offset] = true;
+
+        match movesets[offset] {
+            0..=27 | 0x002C | 0x003B | 0x003C => offset += 1,
+            0x003A | 0x003D | 0x003E => {
+                let Some(target) = movesets.get(offset + 1) else {
+                    return Err(VmError::MissingOperand(offset));
+                };
+                jumps.push((offset, usize::from(*target)));
+                offset += 2;
+            }
+            moveset => return Err(VmError::InvalidMoveset { offset, moveset }),
+        }
+    }
+    starts[movesets.len()] = true; // jumping to the end finishes the program
+
+    for (offset, target) in jumps {
+        if !starts.get(target).copied().unwrap_or(false) {
+            return Err(VmError::InvalidJumpTarget { offset, target });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::vm::VM;
+
+    fn code(movesets: &[u16]) -> Code {
+        Code { movesets: movesets.to_vec(), ..Code::default() }
+    }
+
+    #[test]
+    fn compiled_code_passes() {
+        // R U, a call to the end and a jump back to the start
+        assert_eq!(verify(&code(&[0xB0, 6, 0, 0x3B, 0x3A, 8, 0x3E, 1])), Ok(()));
+    }
+
+    #[test]
+    fn missing_header() {
+        assert_eq!(verify(&code(&[])), Err(VmError::CorruptBytecode));
+        assert_eq!(verify(&code(&[6, 0x3B])), Err(VmError::CorruptBytecode));
+    }
+
+    #[test]
+    fn jump_without_operand_at_the_end() {
+        for jump in [0x3A, 0x3D, 0x3E] {
+            assert_eq!(verify(&code(&[0xB0, 6, jump])), Err(VmError::MissingOperand(2)));
+        }
+    }
+
+    #[test]
+    fn truncated_code_is_not_loaded() {
+        let mut vm = VM::new();
+        assert_eq!(vm.load(code(&[0xB0, 0x3E])), Err(VmError::MissingOperand(1)));
+    }
+
+    #[test]
+    fn jump_into_an_operand() {
+        // the second jump targets the operand of the first
+        assert_eq!(verify(&code(&[0xB0, 0x3E, 3, 0x3D, 2])), Err(VmError::InvalidJumpTarget { offset: 3, target: 2 }));
+    }
+
+    #[test]
+    fn moveset_out_of_range() {
+        assert_eq!(verify(&code(&[0xB0, 6, 28])), Err(VmError::InvalidMoveset { offset: 2, moveset: 28 }));
+        // the header only belongs at the start
+        assert_eq!(verify(&code(&[0xB0, 0xB0])), Err(VmError::InvalidMoveset { offset: 1, moveset: 0xB0 }));
+    }
+
+    #[test]
+    fn jump_to_the_end() {
+        // exactly the end finishes the program, past it is nowhere
+        assert_eq!(verify(&code(&[0xB0, 6, 0x3E, 4])), Ok(()));
+        assert_eq!(verify(&code(&[0xB0, 6, 0x3E, 5])), Err(VmError::InvalidJumpTarget { offset: 2, target: 5 }));
+    }
+}
@@ -1,142 +1,824 @@
+use std::collections::HashMap;
+use std::mem;
+use std::time::Instant;
+
+use crate::vm::config::VmConfig;
 use crate::vm::cube::{self, Cube};
-use crate::lang::codegen;
+use crate::vm::decode::{self, Op};
+use crate::vm::error::VmError;
+use crate::vm::io::{Io, StdIo};
+use crate::vm::journal::{Effect, Entry, Journal};
+use crate::vm::limits::{Limit, VmLimits};
+use crate::vm::memory::Memory;
+use crate::vm::snapshot::Snapshot;
+use crate::vm::syscall::{HostFn, Syscall};
+use crate::vm::verify;
+use crate::lang::codegen::Code;
+
+/// what the VM is doing after a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// there is more code to run
+    Running,
+    /// the program counter ran off the end of the code
+    Finished,
+    /// the program stopped itself with HALT, with this exit status
+    Halted(u8),
+    /// stopped before crossing a limit, raise it to resume
+    LimitReached(Limit),
+}
 
 pub struct VM {
-    stack: [Cube; 128],
+    stack: Vec<Cube>,
     stack_pointer: usize,
-    mem: [Cube; 8192], // 8K memory
+    mem: Memory,
     mem_pointer: usize,
 
     program_counter: usize,
     return_stack: Vec<usize>,
+    /// the next instruction stored in memory to run, while EXEC runs them
+    exec_pointer: Option<usize>,
+    /// the exit status, once HALT ran
+    halted: Option<u8>,
+
+    code: Code,
+    /// the code decoded for `run`
+    ops: Vec<Op>,
+    current: Cube,
+    immediate: Cube,
+
+    io: Box<dyn Io>,
+    syscalls: HashMap<u8, HostFn>,
+
+    limits: VmLimits,
+    steps: u64,
+    instructions: u64,
+
+    journal: Option<Journal>,
+    /// what the running step changed, for the journal
+    effects: Vec<Effect>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
 }
 
 impl VM {
     pub fn new() -> VM {
+        VM::with_config(VmConfig::default())
+    }
+
+    pub fn with_config(config: VmConfig) -> VM {
         VM {
-            stack: [Cube::new(); 128],
+            stack: vec![Cube::new(); config.stack_size],
             stack_pointer: 0,
-            mem: [Cube::default(); 8192],
+            mem: Memory::new(config.memory_size),
             mem_pointer: 1, // 0 address = null
 
             program_counter: 1,
             return_stack: vec![],
+            exec_pointer: None,
+            halted: None,
+
+            code: Code::default(),
+            ops: vec![],
+            current: Cube::new(),
+            immediate: Cube::new(),
+
+            io: Box::new(StdIo),
+            syscalls: HashMap::new(),
+
+            limits: VmLimits::default(),
+            steps: 0,
+            instructions: 0,
+
+            journal: None,
+            effects: vec![],
         }
     }
 
-    // this initializes the memory 
-    pub fn interpret_code(&mut self, code: codegen::Code) {
-
-        println!("{:?}", &code.movesets);
-
-        assert!(code.movesets[0] == 0x00B0, "Corrupt bytecode!");
-
-        let mut immediate = Cube::new();
-        let mut current = Cube::new();
-
-        while self.program_counter < code.movesets.len() {
-
-            if self.return_stack.len() > 1024 {
-                panic!("overflow of return stack (len > 1024)");
-            }
-
-            match code.movesets[self.program_counter] {
-                0 => current.twist_u(),
-                1 => current.twist_u_prime(),
-                2 => current.twist_u2(),
-                3 => current.twist_f(),
-                4 => current.twist_f_prime(),
-                5 => current.twist_f2(),
-                6 => current.twist_r(),
-                7 => current.twist_r_prime(),
-                8 => current.twist_r2(),
-                9 => current.twist_b(),
-                10 => current.twist_b_prime(),
-                11 => current.twist_b2(),
-                12 => current.twist_l(),
-                13 => current.twist_l_prime(),
-                14 => current.twist_l2(),
-                15 => current.twist_d(),
-                16 => current.twist_d_prime(),
-                17 => current.twist_d2(),
-
-                18 => current.rotate_x(),
-                19 => current.rotate_x_prime(),
-                20 => current.rotate_x2(),
-                21 => current.rotate_y(),
-                22 => current.rotate_y_prime(),
-                23 => current.rotate_y2(),
-                24 => current.rotate_z(),
-                25 => current.rotate_z_prime(),
-                26 => current.rotate_z2(),
-
-                0x003A => { // :
-                    self.return_stack.push(self.program_counter);
-                    self.program_counter = code.movesets[self.program_counter+1].into() // jump
+    /// replaces where PUTC/PUTN write to and GETC/GETN read from
+    pub fn set_io(&mut self, io: Box<dyn Io>) {
+        self.io = io;
+    }
+
+    /// makes SYSCALL call `function` when its immediate sums to `number`, replacing what was there
+    pub fn register_syscall(&mut self, number: u8, function: impl FnMut(&mut Syscall<'_>) -> Result<(), VmError> + 'static) {
+        self.syscalls.insert(number, Box::new(function));
+    }
+
+    /// replaces the limits, also used to raise them and resume
+    pub fn set_limits(&mut self, limits: VmLimits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &VmLimits {
+        &self.limits
+    }
+
+    /// verifies a program, loads it and points the program counter at its start.
+    ///
+    /// the stack and memory are left as they are
+    pub fn load(&mut self, code: Code) -> Result<(), VmError> {
+        verify::verify(&code)?;
+
+        self.ops = decode::decode(&code);
+        self.code = code;
+        self.program_counter = 1;
+        self.return_stack.clear();
+        self.exec_pointer = None;
+        self.halted = None;
+        self.current = Cube::new();
+        self.immediate = Cube::new();
+        self.clear_journal();
+
+        Ok(())
+    }
+
+    /// copies out the whole state of the VM, apart from its io and limits
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            program_counter: self.program_counter,
+            mem_pointer: self.mem_pointer,
+            exec_pointer: self.exec_pointer,
+            halted: self.halted,
+            steps: self.steps,
+            instructions: self.instructions,
+            current: self.current,
+            immediate: self.immediate,
+            stack: self.stack[..=self.stack_pointer].to_vec(),
+            memory: self.mem.cells(),
+            return_stack: self.return_stack.clone(),
+            code: self.code.clone(),
+        }
+    }
+
+    /// puts the VM back in the state the snapshot was taken in.
+    ///
+    /// the step and instruction counts carry on from the snapshot, and so do the limits on them
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), VmError> {
+        verify::verify(&snapshot.code)?;
+        if snapshot.stack.is_empty() || snapshot.stack.len() > self.stack.len() {
+            return Err(VmError::InvalidSnapshot("stack does not fit"));
+        }
+        if snapshot.memory.iter().any(|(address, _)| *address >= self.mem.len()) {
+            return Err(VmError::InvalidSnapshot("memory does not fit"));
+        }
+        if snapshot.program_counter > snapshot.code.movesets.len() {
+            return Err(VmError::InvalidSnapshot("program counter is past the end of the code"));
+        }
+
+        self.stack.fill(Cube::new());
+        self.stack[..snapshot.stack.len()].copy_from_slice(&snapshot.stack);
+        self.stack_pointer = snapshot.stack.len() - 1;
+
+        self.mem.clear();
+        for (address, cube) in snapshot.memory {
+            self.mem.set(address, cube);
+        }
+        self.mem_pointer = snapshot.mem_pointer;
+
+        self.program_counter = snapshot.program_counter;
+        self.return_stack = snapshot.return_stack;
+        self.exec_pointer = snapshot.exec_pointer;
+        self.halted = snapshot.halted;
+        self.ops = decode::decode(&snapshot.code);
+        self.code = snapshot.code;
+        self.current = snapshot.current;
+        self.immediate = snapshot.immediate;
+        self.steps = snapshot.steps;
+        self.instructions = snapshot.instructions;
+        self.clear_journal();
+
+        Ok(())
+    }
+
+    /// loads the code and runs it to the end
+    pub fn interpret_code(&mut self, code: Code) -> Result<Status, VmError> {
+        self.load(code)?;
+        self.run()
+    }
+
+    /// runs until the end of the code.
+    ///
+    /// without a journal this runs the decoded ops, a fused op at a time,
+    /// dropping down to single steps where a limit could be reached in the middle of one
+    pub fn run(&mut self) -> Result<Status, VmError> {
+        if self.journal.is_some() {
+            return self.run_until(|_| false);
+        }
+
+        while !self.is_finished() {
+            // instructions stored in memory are not decoded
+            let op = match self.exec_pointer {
+                Some(_) => Op::Single,
+                None => self.ops[self.program_counter],
+            };
+            match op {
+                Op::Moves { perm, count, next } if self.fits(count) => {
+                    perm.apply(&mut self.current);
+                    self.steps += count;
+                    self.program_counter = next;
+                }
+                Op::Fresh { cube, count, next } if self.fits(count) => {
+                    self.current = cube;
+                    self.steps += count;
+                    self.program_counter = next;
                 }
-                0x003B => { // ;
-                    self.interpret_cube(current, immediate);
-                    current = Cube::new();
-                    immediate = Cube::new();
+                Op::Submit { opcode, immediate, count, next } if self.fits(count) && self.submit_limit(&opcode).is_none() => {
+                    self.current = Cube::new();
+                    self.immediate = Cube::new();
+                    self.steps += count;
+                    self.instructions += 1;
+                    self.program_counter = next;
+                    self.interpret_cube(opcode, immediate)?;
                 }
-                0x003D => {
-                    if self.stack[self.stack_pointer].sum_face(cube::U) > 0 {
-                        self.return_stack.push(self.program_counter);
-                        self.program_counter = code.movesets[self.program_counter+1].into() // jump conditionally
+                _ => {
+                    if let Status::LimitReached(limit) = self.step()? {
+                        return Ok(Status::LimitReached(limit));
                     }
                 }
-                0x002C => { // ,
-                    immediate = current;
-                    current = Cube::new();
+            }
+        }
+        Ok(self.status())
+    }
+
+    /// whether `count` more steps stay within the step limit and the deadline
+    fn fits(&self, count: u64) -> bool {
+        let limits = &self.limits;
+        limits.max_steps.is_none_or(|max| self.steps + count <= max)
+            && limits.deadline.is_none_or(|deadline| Instant::now() < deadline)
+    }
+
+    /// runs at most `steps` steps
+    pub fn run_for(&mut self, steps: usize) -> Result<Status, VmError> {
+        let mut left = steps;
+        self.run_until(|_| {
+            if left == 0 { return true }
+            left -= 1;
+            false
+        })
+    }
+
+    /// runs until `predicate` holds before a step, a limit is reached or the code ends
+    pub fn run_until(&mut self, mut predicate: impl FnMut(&VM) -> bool) -> Result<Status, VmError> {
+        while !self.is_finished() {
+            if predicate(self) {
+                return Ok(Status::Running);
+            }
+            if let Status::LimitReached(limit) = self.step()? {
+                return Ok(Status::LimitReached(limit));
+            }
+        }
+        Ok(self.status())
+    }
+
+    /// at the end of the code or halted
+    pub fn is_finished(&self) -> bool {
+        self.halted.is_some() || (self.exec_pointer.is_none() && self.program_counter >= self.code.movesets.len())
+    }
+
+    fn status(&self) -> Status {
+        match self.halted {
+            Some(exit_status) => Status::Halted(exit_status),
+            None if self.is_finished() => Status::Finished,
+            None => Status::Running,
+        }
+    }
+
+    /// executes a single moveset: one move, a `,`/`;` submission or a jump.
+    /// while EXEC runs, a step is one instruction stored in memory instead
+    pub fn step(&mut self) -> Result<Status, VmError> {
+        if self.is_finished() {
+            return Ok(self.status());
+        }
+
+        if let Some(limit) = self.limit_hit() {
+            return Ok(Status::LimitReached(limit));
+        }
+
+        let (program_counter, exec_pointer, steps, instructions) = (self.program_counter, self.exec_pointer, self.steps, self.instructions);
+        let result = match exec_pointer {
+            Some(address) => self.execute_stored(address),
+            None => self.execute(self.code.movesets[program_counter]),
+        };
+
+        if let Some(journal) = &mut self.journal {
+            journal.push(Entry { program_counter, exec_pointer, steps, instructions, effects: mem::take(&mut self.effects) });
+        }
+
+        result?;
+        Ok(self.status())
+    }
+
+    fn execute(&mut self, moveset: u16) -> Result<(), VmError> {
+        self.program_counter += 1; // jumps overwrite this
+        self.steps += 1;
+
+        match moveset {
+            0..=26 => apply_move(&mut self.current, moveset),
+
+            0x003A => { // : CALL
+                let target = self.operand()?;
+                self.return_stack.push(self.program_counter + 1); // continue after the operand
+                self.record(Effect::ReturnPush);
+                self.program_counter = target;
+            }
+            0x003B => { // ;
+                let (current, immediate) = (self.current, self.immediate);
+                self.record(Effect::InFlight { current, immediate });
+                self.current = Cube::new();
+                self.immediate = Cube::new();
+                self.instructions += 1;
+                self.interpret_cube(current, immediate)?;
+            }
+            0x003C => self.ret()?, // < RET
+            0x003D => { // = JMP if the popped U face sum > 0
+                let target = self.operand()?;
+                if self.pop_value()? > 0 {
+                    self.program_counter = target;
+                } else {
+                    self.program_counter += 1; // skip the operand
+                }
+            }
+            0x003E => { // > JMP
+                self.program_counter = self.operand()?;
+            }
+            0x002C => { // ,
+                self.record(Effect::InFlight { current: self.current, immediate: self.immediate });
+                self.immediate = self.current;
+                self.current = Cube::new();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// executes the instruction stored at `address` and `address + 1`, a NOP ends EXEC
+    fn execute_stored(&mut self, address: usize) -> Result<(), VmError> {
+        self.steps += 1;
+
+        let (opcode, immediate) = self.stored(address)?;
+        if opcode.sum_face(cube::U) == 0 {
+            self.exec_pointer = None;
+            return Ok(());
+        }
+
+        self.exec_pointer = Some(address + 2); // EXEC overwrites this
+        self.instructions += 1;
+        self.interpret_cube(opcode, immediate).inspect_err(|_| {
+            self.exec_pointer = Some(address); // point at what failed
+        })
+    }
+
+    /// the opcode and immediate cubes of the instruction stored at `address`
+    fn stored(&self, address: usize) -> Result<(Cube, Cube), VmError> {
+        match (self.mem.get(address), self.mem.get(address + 1)) {
+            (Some(opcode), Some(immediate)) => Ok((opcode, immediate)),
+            _ => Err(VmError::MemoryOutOfBounds(address + 1)),
+        }
+    }
+
+    /// starts keeping the last `capacity` steps, so they can be undone with `step_back`
+    pub fn enable_journal(&mut self, capacity: usize) {
+        self.journal = Some(Journal::new(capacity));
+    }
+
+    pub fn disable_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// how many steps can be undone
+    pub fn journal_len(&self) -> usize {
+        self.journal.as_ref().map_or(0, Journal::len)
+    }
+
+    /// undoes the last step, false when the journal has nothing left to undo.
+    ///
+    /// output that was written stays written and input that was read stays read,
+    /// only the state of the VM goes back
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.journal.as_mut().and_then(Journal::pop) else {
+            return false;
+        };
+
+        for effect in entry.effects.into_iter().rev() {
+            match effect {
+                Effect::InFlight { current, immediate } => {
+                    self.current = current;
+                    self.immediate = immediate;
                 }
-                _ => {}
+                Effect::StackSlot { slot, old } => self.stack[slot] = old,
+                Effect::StackPointer(old) => self.stack_pointer = old,
+                Effect::Memory { address, old } => self.mem.set(address, old),
+                Effect::MemPointer(old) => self.mem_pointer = old,
+                Effect::ReturnPush => { self.return_stack.pop(); }
+                Effect::ReturnPop(address) => self.return_stack.push(address),
             }
+        }
 
-            self.program_counter += 1;
+        let moveset = self.code.movesets.get(entry.program_counter).copied();
+        if let (None, Some(moveset @ 0..=26)) = (entry.exec_pointer, moveset) {
+            apply_move(&mut self.current, inverse_move(moveset));
         }
+
+        self.program_counter = entry.program_counter;
+        self.exec_pointer = entry.exec_pointer;
+        self.halted = None; // nothing runs after a HALT, so it is always the step undone
+        self.steps = entry.steps;
+        self.instructions = entry.instructions;
+        true
     }
 
-    fn interpret_cube(&mut self, cube: Cube, immediate: Cube) {
+    /// the history before a load or restore does not apply to what follows
+    fn clear_journal(&mut self) {
+        if let Some(journal) = &mut self.journal {
+            journal.clear();
+        }
+    }
+
+    /// keeps an effect of the running step for the journal
+    fn record(&mut self, effect: Effect) {
+        if self.journal.is_some() {
+            self.effects.push(effect);
+        }
+    }
+
+    /// which limit the next step would cross, checked before anything about it is executed
+    fn limit_hit(&self) -> Option<Limit> {
+        let limits = &self.limits;
+
+        if limits.max_steps.is_some_and(|max| self.steps >= max) {
+            return Some(Limit::Steps);
+        }
+        if limits.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Some(Limit::Deadline);
+        }
+
+        if let Some(address) = self.exec_pointer {
+            return match self.stored(address) {
+                Ok((opcode, _)) if opcode.sum_face(cube::U) != 0 => self.submit_limit(&opcode),
+                _ => None,
+            };
+        }
+
+        match self.code.movesets[self.program_counter] {
+            0x003A if self.return_stack.len() >= limits.max_return_depth => Some(Limit::ReturnDepth),
+            0x003B => self.submit_limit(&self.current),
+            _ => None,
+        }
+    }
+
+    /// which limit submitting `opcode` would cross
+    fn submit_limit(&self, opcode: &Cube) -> Option<Limit> {
+        let limits = &self.limits;
+
+        if limits.max_instructions.is_some_and(|max| self.instructions >= max) {
+            return Some(Limit::Instructions);
+        }
+        // PSH, GETC and GETN push
+        let pushes = matches!(opcode.sum_face(cube::U), 1 | 9 | 10);
+        // without a limit a full stack is the program's bug, `push` fails with StackOverflow
+        if pushes && limits.max_stack_depth.is_some_and(|max| self.stack_pointer >= max) {
+            return Some(Limit::StackDepth);
+        }
+        None
+    }
+
+    /// movesets executed so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// `;` submitted instructions executed so far
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// the loaded program
+    pub fn code(&self) -> &Code {
+        &self.code
+    }
+
+    /// the stack from the bottom up, without the empty slot under it
+    pub fn stack(&self) -> &[Cube] {
+        &self.stack[1..=self.stack_pointer]
+    }
+
+    pub fn stack_pointer(&self) -> usize {
+        self.stack_pointer
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.mem
+    }
+
+    pub fn mem_pointer(&self) -> usize {
+        self.mem_pointer
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    /// the address of the next instruction EXEC runs from memory, `None` when running code
+    pub fn exec_pointer(&self) -> Option<usize> {
+        self.exec_pointer
+    }
+
+    /// the return addresses, the most recent call last
+    pub fn return_stack(&self) -> &[usize] {
+        &self.return_stack
+    }
+
+    /// the cube the moves are applied to
+    pub fn current(&self) -> &Cube {
+        &self.current
+    }
+
+    /// the cube submitted with the last `,`
+    pub fn immediate(&self) -> &Cube {
+        &self.immediate
+    }
+
+    fn interpret_cube(&mut self, cube: Cube, immediate: Cube) -> Result<(), VmError> {
 
         let opcode = cube.sum_face(cube::U);
-        let imm = immediate.sum_face(cube::U);
 
         match opcode {
             0 => {} // NOP
-            1 => { // PSH immediate
-                if self.stack_pointer == 128 { panic!("cannot push full stack!") }
-
-                self.stack_pointer += 1;
-                self.stack[self.stack_pointer] = immediate;
+            1 => self.push(immediate)?, // PSH immediate
+            2 => { self.pop()?; } // POP
+            3 => self.write_memory(self.mem_pointer, immediate)?, // MEM [mp] = imm
+            // SMS [mp] -> [sp] and SSM [sp] -> [mp] both end up swapping
+            4 | 5 => {
+                let address = self.mem_address()?;
+                self.record(Effect::Memory { address, old: self.mem.get(address).unwrap_or_default() });
+                self.record(Effect::StackSlot { slot: self.stack_pointer, old: self.stack[self.stack_pointer] });
+                mem::swap(&mut self.stack[self.stack_pointer], self.mem.cell_mut(address));
             }
-            2 => { // POP
-                if self.stack_pointer == 0 { panic!("cannot pop empty stack!") }
-
-                self.stack[self.stack_pointer] = Cube::new();
-                self.stack_pointer -= 1;
+            6 => self.ret()?, // RET
+            7 => { // PUTC pop [sp], write it as a character
+                let value = self.pop_value()?;
+                self.io.write(&[value]);
             }
-            3 => self.mem[self.mem_pointer] = immediate, // MEM [mp] = imm
-            4 => { // SMS [mp] -> [sp]
-                let t = self.stack[self.stack_pointer];
-                self.stack[self.stack_pointer] = self.mem[self.mem_pointer];
-                self.mem[self.mem_pointer] = t; 
+            8 => { // PUTN pop [sp], write it as a decimal number
+                let value = self.pop_value()?;
+                self.io.write(value.to_string().as_bytes());
             }
-            5 => { // SSM [sp] -> [mp]
-                let t = self.mem[self.mem_pointer];
-                self.mem[self.mem_pointer] = self.stack[self.stack_pointer];
-                self.stack[self.stack_pointer] = t;   
+            9 => { // GETC read a character, push it
+                let value = self.io.read_byte().unwrap_or(0);
+                self.push(Cube::with_value(value))?;
             }
-            6 => { // RET
-                match self.return_stack.pop() {
-                    Some(i) => self.program_counter = i,
-                    None => panic!("popped empty return stack!"),
-                }
+            10 => { // GETN read a decimal number, push it
+                let value = self.read_number();
+                self.push(Cube::with_value(value))?;
+            }
+            11 => self.syscall(immediate.sum_face(cube::U))?, // SYSCALL the host function numbered imm
+            12 => { // STORE pop [sp] as the opcode, write it and imm to [mp] and [mp+1]
+                let opcode = self.pop()?;
+                self.write_memory(self.mem_pointer, opcode)?;
+                self.write_memory(self.mem_pointer + 1, immediate)?;
+                self.record(Effect::MemPointer(self.mem_pointer));
+                self.mem_pointer += 1; // past the pair with the step below
+            }
+            13 => { // EXEC the instructions stored from imm cells before [mp]
+                let back = immediate.sum_face(cube::U);
+                let Some(address) = self.mem_pointer.checked_sub(back.into()) else {
+                    return Err(VmError::ExecBeforeMemory { mem_pointer: self.mem_pointer, back });
+                };
+                self.exec_pointer = Some(address);
+            }
+            14 => { // HALT with [sp] as the exit status, 0 on an empty stack
+                let exit_status = if self.stack_pointer == 0 { 0 } else { self.pop_value()? };
+                self.halted = Some(exit_status);
             }
             _ => {}
         }
 
-        // TODO: add labels
-        //self.mem[self.mem_pointer] = opcode; // write the opcode to memory for use later
+        self.record(Effect::MemPointer(self.mem_pointer));
         self.mem_pointer += 1;
+
+        Ok(())
+    }
+
+    /// calls a registered host function
+    fn syscall(&mut self, number: u8) -> Result<(), VmError> {
+        // out of the map while it runs, so it can borrow the VM
+        let Some(mut function) = self.syscalls.remove(&number) else {
+            return Err(VmError::UnknownSyscall(number));
+        };
+        let result = function(&mut Syscall { vm: self });
+        self.syscalls.insert(number, function);
+        result
+    }
+
+    /// the jump target following the jump that was just read
+    fn operand(&self) -> Result<usize, VmError> {
+        match self.code.movesets.get(self.program_counter) {
+            Some(target) => Ok((*target).into()),
+            None => Err(VmError::MissingOperand(self.program_counter - 1)),
+        }
+    }
+
+    /// the memory pointer, if it still points into memory
+    fn mem_address(&self) -> Result<usize, VmError> {
+        if self.mem_pointer >= self.mem.len() {
+            return Err(VmError::MemoryOutOfBounds(self.mem_pointer));
+        }
+        Ok(self.mem_pointer)
+    }
+
+    /// jumps back to the address on top of the return stack
+    fn ret(&mut self) -> Result<(), VmError> {
+        match self.return_stack.pop() {
+            Some(i) => {
+                self.record(Effect::ReturnPop(i));
+                self.program_counter = i
+            }
+            None => return Err(VmError::ReturnStackUnderflow),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write_memory(&mut self, address: usize, cube: Cube) -> Result<(), VmError> {
+        let Some(old) = self.mem.get(address) else {
+            return Err(VmError::MemoryOutOfBounds(address));
+        };
+        self.record(Effect::Memory { address, old });
+        self.mem.set(address, cube);
+        Ok(())
+    }
+
+    pub(crate) fn set_stack_slot(&mut self, slot: usize, cube: Cube) {
+        self.record(Effect::StackSlot { slot, old: self.stack[slot] });
+        self.stack[slot] = cube;
+    }
+
+    pub(crate) fn push(&mut self, cube: Cube) -> Result<(), VmError> {
+        if self.stack_pointer + 1 == self.stack.len() { return Err(VmError::StackOverflow) }
+
+        self.record(Effect::StackPointer(self.stack_pointer));
+        self.stack_pointer += 1;
+        self.record(Effect::StackSlot { slot: self.stack_pointer, old: self.stack[self.stack_pointer] });
+        self.stack[self.stack_pointer] = cube;
+        Ok(())
+    }
+
+    pub(crate) fn pop(&mut self) -> Result<Cube, VmError> {
+        if self.stack_pointer == 0 { return Err(VmError::StackUnderflow) }
+
+        let cube = mem::replace(&mut self.stack[self.stack_pointer], Cube::new());
+        self.record(Effect::StackSlot { slot: self.stack_pointer, old: cube });
+        self.record(Effect::StackPointer(self.stack_pointer));
+        self.stack_pointer -= 1;
+        Ok(cube)
+    }
+
+    /// pops the top of the stack and returns the sum of its U face
+    fn pop_value(&mut self) -> Result<u8, VmError> {
+        Ok(self.pop()?.sum_face(cube::U))
+    }
+
+    /// skips leading whitespace, then reads digits up to the first non-digit, which is left in the input.
+    /// the value wraps around at 256, no digits at all reads as 0
+    fn read_number(&mut self) -> u8 {
+        let mut value = 0u8;
+
+        while self.io.peek_byte().is_some_and(|b| b.is_ascii_whitespace()) {
+            self.io.read_byte();
+        }
+        while let Some(digit @ b'0'..=b'9') = self.io.peek_byte() {
+            value = value.wrapping_mul(10).wrapping_add(digit - b'0');
+            self.io.read_byte();
+        }
+
+        value
+    }
+}
+
+/// applies a move moveset (0 to 26) to the cube, anything else leaves it as is
+pub fn apply_move(cube: &mut Cube, moveset: u16) {
+    match moveset {
+        0 => cube.twist_u(),
+        1 => cube.twist_u_prime(),
+        2 => cube.twist_u2(),
+        3 => cube.twist_f(),
+        4 => cube.twist_f_prime(),
+        5 => cube.twist_f2(),
+        6 => cube.twist_r(),
+        7 => cube.twist_r_prime(),
+        8 => cube.twist_r2(),
+        9 => cube.twist_b(),
+        10 => cube.twist_b_prime(),
+        11 => cube.twist_b2(),
+        12 => cube.twist_l(),
+        13 => cube.twist_l_prime(),
+        14 => cube.twist_l2(),
+        15 => cube.twist_d(),
+        16 => cube.twist_d_prime(),
+        17 => cube.twist_d2(),
+
+        18 => cube.rotate_x(),
+        19 => cube.rotate_x_prime(),
+        20 => cube.rotate_x2(),
+        21 => cube.rotate_y(),
+        22 => cube.rotate_y_prime(),
+        23 => cube.rotate_y2(),
+        24 => cube.rotate_z(),
+        25 => cube.rotate_z_prime(),
+        26 => cube.rotate_z2(),
+        _ => {}
+    }
+}
+
+/// the move that undoes a move moveset: X becomes X', X' becomes X and X2 and `*` stay as they are
+pub fn inverse_move(moveset: u16) -> u16 {
+    match (moveset, moveset % 3) {
+        (27.., _) => moveset,
+        (_, 0) => moveset + 1,
+        (_, 1) => moveset - 1,
+        _ => moveset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::io::test_vm;
+
+    #[test]
+    fn exec_reaches_code_stored_past_255() {
+        // NOPs to move the memory pointer on
+        let source = "* ;\n".repeat(300) + "
+            U F' , F D F' ;     // PSH the PUTN opcode
+            * , R' ;            // STORE it at 302 and 303
+            U' F X2 , F D F' ;  // PSH 101
+            R , U B' L' ;       // EXEC 3 cells back
+        ";
+        let (mut vm, io) = test_vm(&source, b"");
+        assert_eq!(vm.run(), Ok(Status::Finished));
+        assert_eq!(vm.memory().get(302).map(|c| c.sum_face(cube::U)), Some(8));
+        assert_eq!(io.output_string(), "101");
+    }
+
+    #[test]
+    fn exec_before_the_start_of_memory() {
+        let (mut vm, _) = test_vm("X2 , U B' L' ;", b"");
+        assert_eq!(vm.run(), Err(VmError::ExecBeforeMemory { mem_pointer: 1, back: 144 }));
+    }
+
+    #[test]
+    fn pushing_onto_a_full_stack_is_an_error() {
+        // slot 0 is always empty, so the 128th push does not fit
+        let (mut vm, _) = test_vm(&"* , F D F' ;\n".repeat(128), b"");
+        assert_eq!(vm.run(), Err(VmError::StackOverflow));
+        assert_eq!(vm.stack_pointer(), 127);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn stack_depth_limit_stops_before_the_push() {
+        let (mut vm, _) = test_vm(&"* , F D F' ;\n".repeat(3), b"");
+        vm.set_limits(VmLimits { max_stack_depth: Some(2), ..VmLimits::default() });
+        assert_eq!(vm.run(), Ok(Status::LimitReached(Limit::StackDepth)));
+        assert_eq!(vm.stack_pointer(), 2);
+
+        vm.set_limits(VmLimits::default());
+        assert_eq!(vm.run(), Ok(Status::Finished));
+        assert_eq!(vm.stack_pointer(), 3);
+    }
+
+    #[test]
+    fn step_back_restores_every_earlier_state() {
+        let source = "
+            U F' , F D F' ;             // PSH the PUTN opcode
+            * , R' ;                    // STORE it at 2 and 3
+            U' F X2 , F D F' ;          // PSH 101
+            R , U B' L' ;               // EXEC 3 cells back, at 2, which prints 101
+            :push1
+            =skip                       // pops the 1 and jumps
+            * , F D F' ;
+            skip: F' R , F D F' ; R' F' ;   // HALT with 7
+            push1: F D F' , F D F' ; <
+        ";
+        let (mut vm, io) = test_vm(source, b"");
+        vm.enable_journal(1024);
+
+        let mut snapshots = vec![];
+        while !vm.is_finished() {
+            snapshots.push(vm.snapshot());
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.run(), Ok(Status::Halted(7)));
+        assert_eq!(io.output_string(), "101");
+
+        while let Some(snapshot) = snapshots.pop() {
+            assert!(vm.step_back());
+            assert_eq!(vm.snapshot(), snapshot);
+        }
+        assert!(!vm.step_back());
+    }
+}